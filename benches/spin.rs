@@ -0,0 +1,84 @@
+//!
+//! compare the spin policies under a few kinds of load, run with
+//! cargo bench --bench spin
+//!
+use cura::{Cura,SpinPolicy};
+use std::time::{Duration,Instant};
+
+const POLICIES:[SpinPolicy;6]=[
+    SpinPolicy::Spin(4),
+    SpinPolicy::Spin(100),
+    SpinPolicy::Backoff(8),
+    SpinPolicy::Yield(4),
+    SpinPolicy::Yield(20),
+    SpinPolicy::Park,
+];
+///
+/// keep the lock for a while
+///
+fn busy(n:u32)
+{
+    for _ in 0..n
+    {
+        std::hint::spin_loop();
+    }
+}
+///
+/// threads hammering one Cura, the first writers of them writing
+/// and the rest reading, returns the best time per lock of a few
+/// runs
+///
+fn run(policy:SpinPolicy,threads:usize,writers:usize,ops:usize,hold:u32)->Duration
+{
+    let mut best=Duration::MAX;
+    for _ in 0..3
+    {
+        let s=Cura::new(0u64);
+        s.set_spin_policy(Some(policy));
+        let start=Instant::now();
+        let handles:Vec<_>=(0..threads).map(|i|{
+            let s=s.clone();
+            std::thread::spawn(move||{
+                for _ in 0..ops
+                {
+                    if i<writers
+                    {
+                        let mut w=s.write();
+                        *w+=1;
+                        busy(hold);
+                    }else{
+                        let r=s.read();
+                        busy(hold);
+                        std::hint::black_box(*r);
+                    }
+                }
+            })
+        }).collect();
+        for h in handles
+        {
+            h.join().unwrap();
+        }
+        best=best.min(start.elapsed()/(threads*ops) as u32);
+    }
+    best
+}
+fn main()
+{
+    let cores=std::thread::available_parallelism().map_or(1,|n|n.get());
+    let loads=[
+        ("short holds, a thread per core",cores,cores/2+1,20000,10),
+        ("long holds, a thread per core",cores,cores/2+1,2000,2000),
+        ("short holds, 4 threads per core",cores*4,cores*2,5000,10),
+        ("mostly reads, 4 threads per core",cores*4,1,5000,100),
+    ];
+    println!("{} cores",cores);
+    for (name,threads,writers,ops,hold) in loads
+    {
+        println!("{}:",name);
+        for policy in POLICIES
+        {
+            let t=run(policy,threads,writers,ops,hold);
+            println!("    {:<16} {:>8} ns/lock",format!("{:?}",policy),t.as_nanos());
+        }
+    }
+}
@@ -0,0 +1,97 @@
+//!
+//! how long a crowd of readers queued behind a writer takes to
+//! get in once the writer lets go, run with
+//! cargo bench --bench wakeup
+//!
+use cura::Cura;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize,Ordering::SeqCst};
+use std::time::{Duration,Instant};
+
+///
+/// readers queue up behind a writelock, time from the release
+/// until the last of them has the readlock
+///
+fn release_to_readers(readers:usize,rounds:usize)->Duration
+{
+    let s=Cura::new(0usize);
+    let go=Arc::new(AtomicUsize::new(0));
+    let arrived=Arc::new(AtomicUsize::new(0));
+    let handles:Vec<_>=(0..readers).map(|_|{
+        let (s,go,arrived)=(s.clone(),go.clone(),arrived.clone());
+        std::thread::spawn(move||{
+            for round in 1..=rounds
+            {
+                while go.load(SeqCst)<round
+                {
+                    std::thread::yield_now();
+                }
+                let r=s.read();
+                assert_eq!(*r,round);
+                arrived.fetch_add(1,SeqCst);
+            }
+        })
+    }).collect();
+    let mut total=Duration::ZERO;
+    for round in 1..=rounds
+    {
+        let mut w=s.write();
+        *w=round;
+        go.store(round,SeqCst);
+        //  give them time to get into the queue
+        std::thread::sleep(Duration::from_millis(2));
+        let start=Instant::now();
+        drop(w);
+        while arrived.load(SeqCst)<readers*round
+        {
+            std::thread::yield_now();
+        }
+        total+=start.elapsed();
+    }
+    for h in handles
+    {
+        h.join().unwrap();
+    }
+    total/rounds as u32
+}
+///
+/// one writer and a lot of readers going at it, time per lock
+///
+fn read_heavy(readers:usize,ops:usize)->Duration
+{
+    let s=Cura::new(0usize);
+    let start=Instant::now();
+    let handles:Vec<_>=(0..=readers).map(|i|{
+        let s=s.clone();
+        std::thread::spawn(move||{
+            for _ in 0..ops
+            {
+                if i==0
+                {
+                    *s.write()+=1;
+                    std::thread::yield_now();
+                }else{
+                    std::hint::black_box(*s.read());
+                }
+            }
+        })
+    }).collect();
+    for h in handles
+    {
+        h.join().unwrap();
+    }
+    start.elapsed()/((readers+1)*ops) as u32
+}
+fn main()
+{
+    for readers in [4,16,64]
+    {
+        println!("{:>3} readers queued behind a writer: {:>8} ns from release to all in",
+                 readers,release_to_readers(readers,200).as_nanos());
+    }
+    for readers in [4,16]
+    {
+        println!("{:>3} readers and a writer: {:>8} ns/lock",
+                 readers,read_heavy(readers,20000).as_nanos());
+    }
+}
@@ -1,15 +1,33 @@
 #![warn(missing_docs)]
+#![cfg_attr(feature="coerce_unsized",feature(coerce_unsized,unsize))]
 //! An attempt at creating an Arc-RwLock combination that is straightforward
 //! to use and no hassle , instead of worrying about being fast and lean.
 //!
 //! * cloning referefences works like Arc
+//! * downgrade() gives a WeakCura like Arc's Weak, to break cycles
+//! * lock_state(), waiter_count() and strong_count() tell what is
+//!   going on, for health checks and tests
 //! * made for sharing objects between threads without worry
 //! * locking things works like RwLock with write() or read()
-//! * it spins a few times and then queues if a lock is not obtained
+//! * read_owned() and write_owned() give guards that can be returned,
+//!   stored and sent to other threads
+//! * lock_all() locks several Curae at once in a deterministic order
+//! * comparing two Curae locks them in that same order, and ByAddress
+//!   compares and hashes them by identity instead
+//! * it spins a few times and then queues if a lock is not obtained,
+//!   SpinPolicy says how many times and how
+//! * with_policy() picks fifo, writer- or reader-preferring fairness
+//! * guards can unlock_fair() or bump() to hand the lock to the next
+//!   in line, or let go of it for a while with unlocked()
+//! * unsize! turns a Cura<T> into a Cura<dyn Trait> after the fact
 //! * miri seems to be happy , so i trust it doesnt leak too much memory etc.
 //! * requires that everything you stick into it is Send+Sync
 //! * no need to constantly .unwrap() things instead it will just
 //!   block forever or blow up
+//! * build with the deadlock_detection feature to have it blow up
+//!   with the threads involved instead of blocking forever. a guard
+//!   sent to another thread then still counts as held by the one
+//!   that took it, so waiting for it there blows up too
 //!
 //! # Example
 //! ```
@@ -38,6 +56,8 @@
 //! // you can do straight "from_box" but currently its impossible to
 //! // "alter" unsized types
 //! let foo2:Cura<dyn Foo>=Cura::from_box(Box::new(FF{i:2}));
+//! // or make one the usual way and turn it into a Cura<dyn Foo> later
+//! let foo3:Cura<dyn Foo>=cura::unsize!(Cura::new(FF{i:3}),dyn Foo);
 //! let foo:Cura<Box<dyn Foo>>=Cura::new(Box::new(t));
 //! let a=foo.clone();
 //! let b=foo.clone();
@@ -69,15 +89,38 @@
 //! ```
 use std::ops::{Deref,DerefMut};
 use std::ptr::NonNull;
-use std::sync::atomic::{AtomicUsize,AtomicI32,AtomicU32};
+use std::mem::ManuallyDrop;
+use std::sync::atomic::{AtomicBool,AtomicUsize,AtomicU32,AtomicPtr,fence};
+use std::sync::{LockResult,PoisonError};
 use std::sync::atomic::Ordering::{Acquire, Relaxed, Release,SeqCst};
 use std::cell::UnsafeCell;
-use std::thread::Thread;
-use std::time::{SystemTime,UNIX_EPOCH};
-use std::marker::PhantomData;
-const LOCKED:i32=-999;
-const FREE:i32=0;
-const LOCKQUEUE:u32=u32::MAX/2;
+use std::alloc::Layout;
+use std::thread::{Thread,ThreadId};
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context,Poll,Waker};
+use std::time::{Duration,Instant};
+use std::marker::{PhantomData,PhantomPinned};
+/*  the low bit of the queue head, set while someone works on the queue*/
+const QUEUELOCKED:usize=1;
+/*  the bits of the state word*/
+const PARKED:usize=1; //someone is in the queue or just woken from it
+const WRITERPARKED:usize=1<<1; //a writer is in the queue
+const WRITER:usize=1<<2; //writelocked
+const UPGRADABLE:usize=1<<3; //an upgradable reader holds the lock
+const UPGRADING:usize=1<<4; //that reader waits to upgrade
+const POISONED:usize=1<<5; //a writer panicked while holding the lock
+const REENTRANT:usize=1<<6; //threads already reading may read again
+const WAKEUP:usize=1<<7; //one woken waiter that has not tried the lock yet
+const WAKEUPS:usize=MAXWAKEUPS*WAKEUP; //only touched with the queue locked
+const MAXWAKEUPS:usize=63;
+const READER:usize=1<<13; //one reader, the rest of the word counts them
+                           //which leaves room for 524287 on 32 bit targets
+const READERS:usize=!(READER-1);
+/*  the config word, SpinPolicy of the Cura at the bottom, 0 for the default*/
+const SPINCOUNTBITS:u32=27; //spin counts above 2^27-1 are cut down to that
+const POLICYSHIFT:u32=SPINCOUNTBITS+3; //the two bits of Policy above it
+const SPINBITS:u32=(1<<POLICYSHIFT)-1;
 
 /// a sort of an Arc that will both readwrite lock , be easy to
 /// handle and is cloneable
@@ -92,26 +135,365 @@ pub struct Cura<T: Sync + Send +?Sized> {
     phantom:PhantomData<CuraData<T>>,
     //dummy:i32,
 }
+///
+/// a weak reference to a Cura, does not keep the value alive
+/// but can be upgraded back to a Cura as long as some Cura
+/// still holds it. use this to break cycles in object graphs
+/// ```
+/// use cura::Cura;
+/// let s=Cura::new(1);
+/// let w=s.downgrade();
+/// assert_eq!(*w.upgrade().unwrap().read(),1);
+/// drop(s);
+/// assert!(w.upgrade().is_none());
+/// ```
+pub struct WeakCura<T: Sync + Send +?Sized> {
+    ptr: NonNull<CuraData<T>>,
+    phantom:PhantomData<CuraData<T>>,
+}
+///
+/// what a Cura is locked for, as told by Cura::lock_state()
+/// ```
+/// use cura::{Cura,LockState};
+/// let s=Cura::new(1);
+/// let r=s.read();
+/// assert_eq!(s.lock_state(),LockState::Read(1));
+/// ```
+#[derive(Clone,Copy,Debug,PartialEq,Eq,Hash)]
+pub enum LockState
+{
+    ///
+    /// not locked at all
+    ///
+    Free,
+    ///
+    /// readlocked this many times, upgradable readlocks included
+    ///
+    Read(usize),
+    ///
+    /// writelocked
+    ///
+    Write,
+}
+///
+/// who gets a contended Cura next, chosen once with
+/// Cura::with_policy(). whatever the policy, a lock that is free
+/// and has nobody waiting for it is just taken
+/// ```
+/// use cura::{Cura,Policy};
+/// let s=Cura::with_policy(1,Policy::Fifo);
+/// assert_eq!(s.policy(),Policy::Fifo);
+/// assert_eq!(Cura::new(1).policy(),Policy::Barging);
+/// ```
+#[derive(Clone,Copy,Debug,Default,PartialEq,Eq,Hash)]
+pub enum Policy
+{
+    ///
+    /// the default. newcomers grab the lock whenever they can,
+    /// even past the queue, and only queue after spinning a bit.
+    /// fastest under load, but a thread that keeps losing the
+    /// race has no upper bound on how long it waits
+    ///
+    #[default]
+    Barging,
+    ///
+    /// first come first served. newcomers queue behind anyone
+    /// already waiting and a waiter that loses its wakeup keeps
+    /// its place, so nobody starves. readers that are next to
+    /// each other in the queue get in together, but never past
+    /// a writer that came before them
+    ///
+    Fifo,
+    ///
+    /// writers first. while a writer is waiting no new readers
+    /// get in and queued writers are placed ahead of queued
+    /// readers, so writers never starve but readers can, as long
+    /// as writers keep coming
+    ///
+    WriterPreferring,
+    ///
+    /// readers first. readers get in whenever the lock is not
+    /// writelocked, no matter who is waiting, and queued readers
+    /// are placed ahead of queued writers. readers never wait for
+    /// a waiting writer, but writers can starve as long as
+    /// readers keep overlapping
+    ///
+    ReaderPreferring,
+}
+impl Policy
+{
+    ///
+    /// the policy bits of the config word
+    ///
+    fn bits(self)->u32
+    {
+        (match self {
+            Policy::Barging=>0,
+            Policy::Fifo=>1,
+            Policy::WriterPreferring=>2,
+            Policy::ReaderPreferring=>3,
+        })<<POLICYSHIFT
+    }
+    fn from_bits(x:u32)->Policy
+    {
+        match x>>POLICYSHIFT {
+            0=>Policy::Barging,
+            1=>Policy::Fifo,
+            2=>Policy::WriterPreferring,
+            _=>Policy::ReaderPreferring,
+        }
+    }
+}
+///
+/// what a thread that did not get the lock does before giving
+/// up and parking in the queue. set it for all Curae with
+/// set_default_spin_policy() or for one with set_spin_policy().
+/// counts above 2^27-1 are cut down to that
+/// ```
+/// use cura::{Cura,SpinPolicy};
+/// let s=Cura::new(1);
+/// s.set_spin_policy(Some(SpinPolicy::Yield(10)));
+/// assert_eq!(s.spin_policy(),SpinPolicy::Yield(10));
+/// s.set_spin_policy(None); //back to the default
+/// assert_eq!(s.spin_policy(),cura::default_spin_policy());
+/// ```
+#[derive(Clone,Copy,Debug,PartialEq,Eq,Hash)]
+pub enum SpinPolicy
+{
+    ///
+    /// retry this many times with a spin_loop() hint in between,
+    /// the default is Spin(4)
+    ///
+    Spin(u32),
+    ///
+    /// retry this many times, spinning twice as long each time
+    /// up to 1024 spin_loop()s. good when the lock is held for
+    /// a bit longer but parking is still too slow
+    ///
+    Backoff(u32),
+    ///
+    /// retry this many times with a thread::yield_now() in
+    /// between, for when there are more threads than cores and
+    /// spinning only keeps the holder from running
+    ///
+    Yield(u32),
+    ///
+    /// dont retry, park right away
+    ///
+    Park,
+}
+impl Default for SpinPolicy
+{
+    fn default()->SpinPolicy
+    {
+        SpinPolicy::Spin(4)
+    }
+}
+impl SpinPolicy
+{
+    ///
+    /// pack into an u32 for keeping in an atomic, with the count
+    /// in the given number of bits and the kind of policy in the
+    /// three above. 0 is left for "not set"
+    ///
+    const fn encode(self,bits:u32)->u32
+    {
+        let (tag,n)=match self {
+            SpinPolicy::Spin(n)=>(1,n),
+            SpinPolicy::Backoff(n)=>(2,n),
+            SpinPolicy::Yield(n)=>(3,n),
+            SpinPolicy::Park=>(4,0),
+        };
+        let count=(1<<bits)-1;
+        let n=if n>count {count} else {n};
+        tag<<bits|n
+    }
+    fn decode(x:u32,bits:u32)->Option<SpinPolicy>
+    {
+        let n=x&((1<<bits)-1);
+        match x>>bits {
+            0=>None,
+            1=>Some(SpinPolicy::Spin(n)),
+            2=>Some(SpinPolicy::Backoff(n)),
+            3=>Some(SpinPolicy::Yield(n)),
+            _=>Some(SpinPolicy::Park),
+        }
+    }
+    ///
+    /// have we retried enough times
+    ///
+    fn done(self,loops:u32)->bool
+    {
+        match self {
+            SpinPolicy::Spin(n)|SpinPolicy::Backoff(n)|SpinPolicy::Yield(n)=>loops>=n,
+            SpinPolicy::Park=>true,
+        }
+    }
+    ///
+    /// pass the time before retry number loops
+    ///
+    fn wait(self,loops:u32)
+    {
+        match self {
+            SpinPolicy::Spin(_)=>std::hint::spin_loop(),
+            SpinPolicy::Backoff(_)=>{
+                for _ in 0..1u32<<loops.min(10)
+                {
+                    std::hint::spin_loop();
+                }
+            },
+            SpinPolicy::Yield(_)=>std::thread::yield_now(),
+            SpinPolicy::Park=>{},
+        }
+    }
+}
+static DEFAULT_SPIN_POLICY:AtomicU32=AtomicU32::new(SpinPolicy::Spin(4).encode(SPINCOUNTBITS));
+///
+/// set the spin policy of all Curae that dont have their own
+/// ```
+/// use cura::SpinPolicy;
+/// cura::set_default_spin_policy(SpinPolicy::Backoff(6));
+/// assert_eq!(cura::default_spin_policy(),SpinPolicy::Backoff(6));
+/// cura::set_default_spin_policy(SpinPolicy::default());
+/// ```
+pub fn set_default_spin_policy(policy:SpinPolicy)
+{
+    DEFAULT_SPIN_POLICY.store(policy.encode(SPINCOUNTBITS),Relaxed);
+}
+///
+/// the spin policy of all Curae that dont have their own
+///
+pub fn default_spin_policy()->SpinPolicy
+{
+    SpinPolicy::decode(DEFAULT_SPIN_POLICY.load(Relaxed),SPINCOUNTBITS).unwrap_or_default()
+}
+///
+/// turn a Cura<T> or a WeakCura<T> into one of an unsized type that
+/// T coerces to, like a trait object or a slice. this is what
+/// `let c:Cura<dyn Trait>=c;` would do if stable rust allowed it,
+/// which it does with the coerce_unsized feature on nightly
+/// ```
+/// use cura::{Cura,unsize};
+/// trait Foo:Send+Sync{ fn get(&self)->i32; }
+/// struct FF(i32);
+/// impl Foo for FF{ fn get(&self)->i32{self.0} }
+/// let a=Cura::new(FF(1));
+/// let b=unsize!(a.clone(),dyn Foo);
+/// let c:Cura<[u8]>=unsize!(Cura::new([1,2,3]),[u8]);
+/// assert_eq!(b.read().get(),1);
+/// assert_eq!(c.read().len(),3);
+/// ```
+/// only coercions get through, an `as` cast that would change
+/// the size does not compile
+/// ```compile_fail
+/// use cura::{Cura,unsize};
+/// let a=unsize!(Cura::new(1u8),u64);
+/// ```
+#[macro_export]
+macro_rules! unsize {
+    ($cura:expr,$t:ty) => {
+        match $cura {
+            //  safe as the pointer is only ever coerced
+            cura=>unsafe{cura.__unsize(|p|{let p:*mut $t=p; p})},
+        }
+    };
+}
+///
+/// a pointer with the size or vtable of ptr but the given address,
+/// made by writing over the address part of it
+///
+fn with_addr<T:?Sized>(mut ptr:*mut T,addr:*mut u8)->*mut T
+{
+    unsafe{std::ptr::write(&mut ptr as *mut *mut T as *mut *mut u8,addr);}
+    ptr
+}
+#[repr(C)]
 struct CuraData<T: Sync + Send+?Sized> {
-    data: UnsafeCell<Box<T>>,
-    queuedata:UnsafeCell<QueueData>,
+    head:Header,
+    data:UnsafeCell<T>, //dropped when count hits 0
+}
+///
+/// everything in a CuraData but the value, kept in a struct of
+/// its own so that the layout of an unsized CuraData can be
+/// worked out from it and the value
+///
+struct Header
+{
     count: AtomicUsize,
-    lockcount:AtomicI32, //-999=writeĺock,0=free,>0 readlock count
-    queuecount:AtomicU32, // number of threads,
+    weakcount: AtomicU32, //weak refs +1 held by all the strong ones together
+    config:AtomicU32, //policy and spin policy, see above
+    state:AtomicUsize, //readers,lock and queue bits, see above
+    queue:AtomicPtr<QueueLink>, //first waiter, tagged with QUEUELOCKED
+}
+impl Header
+{
+    fn new(policy:Policy)->Header
+    {
+        Header{
+            count: AtomicUsize::new(1),
+            weakcount: AtomicU32::new(1),
+            config:AtomicU32::new(policy.bits()),
+            state:AtomicUsize::new(0),
+            queue:AtomicPtr::new(std::ptr::null_mut()),
+        }
+    }
+    ///
+    /// count one more weak ref, blowing up well before the count
+    /// could wrap around, like Arc does
+    ///
+    fn add_weak(&self)
+    {
+        if self.weakcount.fetch_add(1,Relaxed)>u32::MAX/2
+        {
+            std::process::abort();
+        }
+    }
 }
+///
+/// the waiter queue as seen by whoever has locked it. the links
+/// live on the stacks of the parked threads and inside the
+/// futures of the waiting tasks, and only the queue lock holder
+/// touches them until they are taken out. the rest is kept in
+/// the first link and in the state word in between
+///
 struct QueueData
 {
     queue:*mut QueueLink,
     endqueue:*mut QueueLink,
+    count:u32, //number of waiters in the queue
+    writers:u32, //how many of those want to write
+    wakeups:usize, //woken out of the queue but not tried the lock yet
+    wake:Wakes, //to be woken once the queue is unlocked
 }
 impl QueueData
 {
     ///
-    /// queue stuff into end of queue
+    /// count in a waiter joining the queue
+    ///
+    fn inc(&mut self,t:LockType)
+    {
+        self.count+=1;
+        if t==LockType::Write
+        {
+            self.writers+=1;
+        }
+    }
+    ///
+    /// count out a waiter taken out of the queue
+    ///
+    fn dec(&mut self,t:LockType)
+    {
+        self.count-=1;
+        if t==LockType::Write
+        {
+            self.writers-=1;
+        }
+    }
+    ///
+    /// queue a link into end of queue
     ///
-    fn enqueue(&mut self,t:LockType)
+    fn enqueue(&mut self,link:*mut QueueLink)
     {
-        let link=Box::leak(Box::new(QueueLink::new(t)));
         let next=self.endqueue;
         if next.is_null()
         {
@@ -121,9 +503,39 @@ impl QueueData
         }
         self.endqueue=link;
     }
-    fn dequeue(&mut self)
+    ///
+    /// queue a link in front of the first link that before() picks,
+    /// or into the end of the queue if it picks none
+    ///
+    fn enqueue_before(&mut self,link:*mut QueueLink,before:impl Fn(LockType)->bool)
+    {
+        //  find the ones to go between
+        let mut prev:*mut QueueLink=std::ptr::null_mut();
+        let mut next=self.queue;
+        unsafe{
+            while !next.is_null() && !before((*next).lock)
+            {
+                prev=next;
+                next=(*next).next;
+            }
+            if prev.is_null()
+            {
+                self.queue=link;
+            }else{
+                (*prev).next=link;
+            }
+            (*link).next=next;
+        }
+        if next.is_null()
+        {
+            self.endqueue=link;
+        }
+    }
+    ///
+    /// unlink the first link
+    ///
+    fn pop(&mut self)->*mut QueueLink
     {
-        //  dequeue
         let me=self.queue;
         unsafe{self.queue=(*self.queue).next;}
         //  if we were the last
@@ -131,31 +543,160 @@ impl QueueData
         {
             self.endqueue=std::ptr::null_mut();
         }
-        unsafe {
-            drop(Box::from_raw(me));
+        me
+    }
+    ///
+    /// unlink a link from anywhere in the queue
+    ///
+    fn remove(&mut self,link:*mut QueueLink)
+    {
+        if self.queue==link
+        {
+            self.pop();
+            return;
+        }
+        //  find the one before us
+        let mut prev=self.queue;
+        unsafe{
+            while !prev.is_null() && (*prev).next!=link
+            {
+                prev=(*prev).next;
+            }
+            if prev.is_null()
+            {
+                panic!("trying to remove a link that is not queued");
+            }
+            (*prev).next=(*link).next;
+            if self.endqueue==link
+            {
+                self.endqueue=prev;
+            }
         }
     }
 }
-#[derive(PartialEq)]
+#[derive(PartialEq,Clone,Copy)]
 enum LockType
 {
     Read,
     Write,
+    Upgradable, //waiting for an upgradable readlock
+    Upgrade,    //holding an upgradable readlock, waiting for readers to leave
+}
+///
+/// how a wait in the queue ended
+///
+#[derive(Clone,Copy,PartialEq,Eq,Debug)]
+enum Wakeup
+{
+    TimedOut, //gave up and left the queue
+    Woken, //taken out of the queue to go for the lock
+    Handed, //taken out of the queue with the lock already ours
+}
+///
+/// whoever is waiting in the queue, either a parked thread
+/// or an async task
+///
+enum Waiter
+{
+    Thread(Thread),
+    Task(Waker),
+}
+impl Waiter
+{
+    fn wake(self)
+    {
+        match self {
+            Waiter::Thread(t)=>t.unpark(),
+            Waiter::Task(w)=>w.wake(),
+        }
+    }
+}
+///
+/// links taken out of the queue, chained through their next
+/// pointers. wakers are user code that may well take locks or
+/// even poll, so they are only woken after the queue is unlocked
+///
+struct Wakes
+{
+    first:*mut QueueLink,
+    last:*mut QueueLink,
+}
+impl Default for Wakes
+{
+    fn default()->Wakes
+    {
+        Wakes{
+            first:std::ptr::null_mut(),
+            last:std::ptr::null_mut(),
+        }
+    }
+}
+impl Wakes
+{
+    fn push(&mut self,link:*mut QueueLink)
+    {
+        unsafe{
+            (*link).next=std::ptr::null_mut();
+            if self.first.is_null()
+            {
+                self.first=link;
+            }else{
+                (*self.last).next=link;
+            }
+        }
+        self.last=link;
+    }
+    ///
+    /// let each link go before waking its owner, who may return
+    /// and drop it as soon as it is settled
+    ///
+    fn wake(self)
+    {
+        let mut link=self.first;
+        while !link.is_null()
+        {
+            unsafe{
+                let next=(*link).next;
+                let waiter=(*link).waiter.take();
+                (*link).settled.store(true,Release);
+                if let Some(waiter)=waiter
+                {
+                    waiter.wake();
+                }
+                link=next;
+            }
+        }
+    }
 }
 struct QueueLink
 {
-    thread:Thread,
+    waiter:Option<Waiter>, //taken out when woken
     lock:LockType,
-    next:*mut QueueLink,
+    woken:bool, //taken out of the queue and told to go for the lock
+    handed:bool, //and the lock was already handed to us on the way
+    settled:AtomicBool, //and whoever took us out is done with the link
+    next:*mut QueueLink, //next in the queue, or in Wakes once out of it
+    //  the rest of the queue data, only kept in the first link
+    endqueue:*mut QueueLink,
+    count:u32,
+    writers:u32,
+    _pin:PhantomPinned, //the queue points at us while we are in it
 }
 impl QueueLink
 {
-    fn new(l:LockType)->QueueLink
+    fn new(l:LockType,waiter:Waiter)->QueueLink
     {
         QueueLink{
-            thread:std::thread::current(),
+            waiter:Some(waiter),
             lock:l,
+            woken:false,
+            handed:false,
+            settled:AtomicBool::new(false),
             next:std::ptr::null_mut(),
+            endqueue:std::ptr::null_mut(),
+            count:0,
+            writers:0,
+            _pin:PhantomPinned,
         }
     }
     /*
@@ -174,121 +715,570 @@ impl <T: Sync + Send> Cura<T> {
     ///     let foo=Cura::new(t); //instead of Arc::new(Mutex::new(t));
     /// ```
     pub fn new(t: T) -> Cura<T> {
-        Self::from_box(Box::new(t))
+        Self::with_policy(t,Policy::default())
     }
-}
-///
-/// Cura public interface
-///
-impl<T:  Sync + Send + ?Sized> Cura<T> {
     ///
-    /// convert from box<T> to Cura<T>
-    ///
-    pub fn from_box(v: Box<T>) -> Cura<T> {
-        let queuedata=UnsafeCell::new(QueueData{
-                queue:std::ptr::null_mut(),
-                endqueue:std::ptr::null_mut(),
-            });
+    /// constructor for a Cura that hands out its lock according
+    /// to the given policy instead of the default one
+    /// ```
+    ///     use cura::{Cura,Policy};
+    ///     let foo=Cura::with_policy(1,Policy::WriterPreferring);
+    ///     *foo.write()+=1;
+    /// ```
+    pub fn with_policy(t:T,policy:Policy)->Cura<T> {
         Cura {
             ptr: NonNull::from(Box::leak(Box::new(CuraData {
-                count: AtomicUsize::new(1),
-                data: UnsafeCell::new(v),
-                lockcount:AtomicI32::new(0),
-                queuecount:AtomicU32::new(0), //
-                queuedata:queuedata,
+                head:Header::new(policy),
+                data:UnsafeCell::new(t),
             }))),
             phantom:PhantomData,
-            //dummy:0,
-        }
-    }
-    ///
-    /// readlock a 'Cura',returning a guard that can be
-    /// dereferenced for read-only operations
-    ///
-    pub fn read(&self)->ReadGuard<T>
-    {
-        //TBD think through these memory orderings
-
-        //  how many times have we looped here...
-        let mut loops=0;
-        loop{
-            let lock=self.data().lockcount.fetch_update(
-                                        SeqCst,
-                                        SeqCst,
-                                        |x|{
-                                            if x>=0{
-                                                Some(x+1)
-                                            }else{
-                                                None
-                                            }
-                                        });
-            match lock {
-                Err(_)=>{/*   its probably writelocked,so we will spin*/
-                    if loops>3 || self.queue_size()>0
-                    {
-                        self.enqueue(LockType::Read);
-                        loops=0;
-                    }else{
-                        loops+=1;
-                        std::hint::spin_loop();
-                    }
-                },
-                Ok(_x)=>{/*    x readers,including us*/
-                    //  let everyone else in from the queue
-                    if self.queue_size()>0
-                    {
-                        self.wakereader();
-                    }
-                    break;
-                },
-            }
-        }
-        ReadGuard{
-            cura:self,
         }
     }
     ///
-    /// writelock a 'Cura' , returning a guard that can be
-    /// dereferenced for write-operations.
-    ///
-    pub fn write(&self)->Guard<T>
+    /// turn a Cura<T> into a Cura<U> that shares the same value and
+    /// lock. only there for the unsize! macro, which can only reach
+    /// public items from other crates, use that instead
+    /// # Safety
+    /// f must return its argument, only coerced. pointing it
+    /// elsewhere is caught, but changing the type is not
+    #[doc(hidden)]
+    pub unsafe fn __unsize<U:Sync+Send+?Sized>(self,f:impl FnOnce(*mut T)->*mut U)->Cura<U>
     {
-        //TBD think through these memory orderings
-        let mut loops=0;
-        loop{
-            let lock=self.data().lockcount.fetch_update(
-                                        SeqCst,
-                                        SeqCst,
-                                        |x|{
-                                            if x==FREE{
-                                                Some(LOCKED)
-                                            }else{
-                                                None
-                                            }
-                                        });
-            match lock {
-                Err(_)=>{/*   its write/readlocked,so we will spin*/
-                    if loops>3 || self.queue_size()>0
-                    {
-                        self.enqueue(LockType::Write);
-                        loops=0;
-                    }else{
-                        loops+=1;
-                        std::hint::spin_loop();
-                    }
-                },
-                Ok(_x)=>{/*    should be just us , writing*/
-                    break;
-                },
-            }
-        }
-        Guard{
-            cura:self,
+        let ptr=self.ptr.as_ptr();
+        let value=std::ptr::addr_of_mut!((*ptr).data) as *mut T;
+        let unsized_value=f(value);
+        assert_eq!(unsized_value as *mut u8,value as *mut u8,"unsize moved the pointer");
+        //  our reference is handed over to the new one
+        std::mem::forget(self);
+        Cura{
+            ptr:NonNull::new_unchecked(with_addr(unsized_value as *mut CuraData<U>,ptr as *mut u8)),
+            phantom:PhantomData,
         }
     }
     ///
-    /// transparently take a writelock, attempt to mutate the value
-    /// and then release the lock
+    /// put a new value in and get the old one back
+    /// ```
+    /// use cura::Cura;
+    /// let t=Cura::new(1);
+    /// assert_eq!(t.replace(2),1);
+    /// assert_eq!(*t.read(),2);
+    /// ```
+    pub fn replace(&self,t:T)->T
+    {
+        std::mem::replace(&mut *self.write(),t)
+    }
+    ///
+    /// put a new value in, dropping the old one
+    ///
+    pub fn set(&self,t:T)
+    {
+        drop(self.replace(t));
+    }
+    ///
+    /// take the value out, leaving the default in its place
+    /// ```
+    /// use cura::Cura;
+    /// let t=Cura::new(vec![1,2]);
+    /// assert_eq!(t.take(),vec![1,2]);
+    /// assert!(t.read().is_empty());
+    /// ```
+    pub fn take(&self)->T
+        where T:Default
+    {
+        std::mem::take(&mut *self.write())
+    }
+    ///
+    /// swap the values of two Curae, the locks are taken in
+    /// address order so swapping a with b while someone else
+    /// swaps b with a will not deadlock
+    /// ```
+    /// use cura::Cura;
+    /// let a=Cura::new(1);
+    /// let b=Cura::new(2);
+    /// a.swap(&b);
+    /// assert_eq!((*a.read(),*b.read()),(2,1));
+    /// ```
+    pub fn swap(&self,other:&Cura<T>)
+    {
+        if self.ptr==other.ptr
+        {
+            return;
+        }
+        lock_all((WriteLock(self),WriteLock(other)),|(mut a,mut b)|{
+            std::mem::swap(&mut *a,&mut *b);
+        });
+    }
+    ///
+    /// get the value out if this is the only Cura pointing to it,
+    /// otherwise get the Cura back
+    /// ```
+    /// use cura::Cura;
+    /// let t=Cura::new(1);
+    /// let t2=t.clone();
+    /// let t=Cura::try_unwrap(t).unwrap_err();
+    /// drop(t2);
+    /// assert_eq!(Cura::try_unwrap(t).unwrap(),1);
+    /// ```
+    pub fn try_unwrap(this:Self)->Result<T,Self>
+    {
+        if this.data().head.count.compare_exchange(1,0,Acquire,Relaxed).is_err()
+        {
+            return Err(this);
+        }
+        Ok(Self::unwrap_last(this))
+    }
+    ///
+    /// get the value out if this is the last Cura pointing to it,
+    /// returns None and just drops this reference otherwise.
+    /// unlike try_unwrap() if two threads race to call this on
+    /// the last two references one of them is guaranteed to win
+    /// ```
+    /// use cura::Cura;
+    /// let t=Cura::new(1);
+    /// let t2=t.clone();
+    /// assert_eq!(Cura::into_inner(t),None);
+    /// assert_eq!(Cura::into_inner(t2),Some(1));
+    /// ```
+    pub fn into_inner(this:Self)->Option<T>
+    {
+        if this.data().head.count.fetch_sub(1,Release)!=1
+        {
+            std::mem::forget(this);
+            return None;
+        }
+        fence(Acquire);
+        Some(Self::unwrap_last(this))
+    }
+    ///
+    /// move the value out of a Cura whose strong count we already
+    /// brought to zero
+    ///
+    fn unwrap_last(this:Self)->T
+    {
+        let v=unsafe{
+            std::ptr::read(this.data().data.get())
+        };
+        //  release the weak reference held by all the strong ones
+        drop(WeakCura{
+            ptr:this.ptr,
+            phantom:PhantomData,
+        });
+        std::mem::forget(this);
+        v
+    }
+}
+///
+/// Cura public interface
+///
+impl<T:  Sync + Send + ?Sized> Cura<T> {
+    ///
+    /// convert from box<T> to Cura<T>
+    ///
+    pub fn from_box(v: Box<T>) -> Cura<T> {
+        Self::from_box_with_policy(v,Policy::default())
+    }
+    ///
+    /// convert from box<T> to Cura<T> with the given policy. the
+    /// value is moved out of the box into the same allocation as
+    /// the lock, so it is only ever one pointer away
+    ///
+    pub fn from_box_with_policy(v: Box<T>,policy:Policy) -> Cura<T> {
+        let value=Box::into_raw(v);
+        unsafe{
+            let (layout,_)=Layout::new::<Header>()
+                .extend(Layout::for_value(&*value))
+                .expect("too big to fit in a Cura");
+            let layout=layout.pad_to_align();
+            let mem=std::alloc::alloc(layout);
+            if mem.is_null()
+            {
+                std::alloc::handle_alloc_error(layout);
+            }
+            let ptr=with_addr(value as *mut CuraData<T>,mem);
+            std::ptr::addr_of_mut!((*ptr).head).write(Header::new(policy));
+            std::ptr::copy_nonoverlapping(value as *const u8,
+                                          std::ptr::addr_of_mut!((*ptr).data) as *mut u8,
+                                          std::mem::size_of_val(&*value));
+            debug_assert_eq!(Layout::for_value(&*ptr),layout);
+            //  free the box without dropping what we just moved out of it
+            drop(Box::from_raw(value as *mut ManuallyDrop<T>));
+            Cura {
+                ptr: NonNull::new_unchecked(ptr),
+                phantom:PhantomData,
+            }
+        }
+    }
+    ///
+    /// readlock a 'Cura',returning a guard that can be
+    /// dereferenced for read-only operations
+    ///
+    pub fn read(&self)->ReadGuard<'_,T>
+    {
+        self.readlock(None);
+        ReadGuard{
+            cura:self,
+            taken:Taken::now(),
+        }
+    }
+    ///
+    /// attempt to readlock a 'Cura' once without spinning or
+    /// queueing, returning None if it is currently writelocked
+    /// or the policy would have us wait behind someone
+    /// ```
+    /// use cura::Cura;
+    /// let s=Cura::new(1);
+    /// let w=s.write();
+    /// assert!(s.try_read().is_none());
+    /// drop(w);
+    /// assert_eq!(*s.try_read().unwrap(),1);
+    /// ```
+    pub fn try_read(&self)->Option<ReadGuard<'_,T>>
+    {
+        if self.may_try(LockType::Read,false) && self.try_readlock()
+        {
+            Some(ReadGuard{
+                cura:self,
+                taken:Taken::now(),
+            })
+        }else{
+            None
+        }
+    }
+    ///
+    /// writelock a 'Cura' , returning a guard that can be
+    /// dereferenced for write-operations.
+    ///
+    pub fn write(&self)->Guard<'_,T>
+    {
+        self.writelock(None);
+        Guard{
+            cura:self,
+            taken:Taken::now(),
+        }
+    }
+    ///
+    /// attempt to writelock a 'Cura' once without spinning or
+    /// queueing, returning None if it is currently locked
+    /// or the policy would have us wait behind someone
+    /// ```
+    /// use cura::Cura;
+    /// let s=Cura::new(1);
+    /// let r=s.read();
+    /// assert!(s.try_write().is_none());
+    /// drop(r);
+    /// *s.try_write().unwrap()=2;
+    /// assert_eq!(*s.read(),2);
+    /// ```
+    pub fn try_write(&self)->Option<Guard<'_,T>>
+    {
+        if self.may_try(LockType::Write,false) && self.try_writelock()
+        {
+            Some(Guard{
+                cura:self,
+                taken:Taken::now(),
+            })
+        }else{
+            None
+        }
+    }
+    ///
+    /// readlock a 'Cura' from async code, the future queues up
+    /// with its waker instead of parking the thread. dropping the
+    /// future before it is done takes it out of the queue
+    /// ```
+    /// use cura::Cura;
+    /// async fn get(s:&Cura<i32>)->i32{
+    ///     *s.read_async().await
+    /// }
+    /// ```
+    pub fn read_async(&self)->ReadFuture<'_,T>
+    {
+        ReadFuture{
+            cura:self,
+            link:None,
+        }
+    }
+    ///
+    /// writelock a 'Cura' from async code, the future queues up
+    /// with its waker instead of parking the thread. dropping the
+    /// future before it is done takes it out of the queue
+    /// ```
+    /// use cura::Cura;
+    /// async fn inc(s:&Cura<i32>){
+    ///     *s.write_async().await+=1;
+    /// }
+    /// ```
+    pub fn write_async(&self)->WriteFuture<'_,T>
+    {
+        WriteFuture{
+            cura:self,
+            link:None,
+        }
+    }
+    ///
+    /// readlock a 'Cura' returning a guard that holds a reference
+    /// of its own instead of borrowing this one, so it can be
+    /// returned, stored or sent to another thread
+    /// ```
+    /// use cura::{Cura,OwnedReadGuard};
+    /// fn get()->OwnedReadGuard<String>{
+    ///     Cura::new("foo".to_string()).read_owned()
+    /// }
+    /// let r=get();
+    /// assert_eq!(std::thread::spawn(move||r.len()).join().unwrap(),3);
+    /// ```
+    pub fn read_owned(&self)->OwnedReadGuard<T>
+    {
+        self.readlock(None);
+        //  the guard may go anywhere, so dont tie the lock to this thread
+        self.released(LockType::Read,held::me());
+        OwnedReadGuard{
+            cura:self.clone(),
+        }
+    }
+    ///
+    /// writelock a 'Cura' returning a guard that holds a reference
+    /// of its own instead of borrowing this one
+    /// ```
+    /// use cura::Cura;
+    /// let s=Cura::new(1);
+    /// let mut w=s.write_owned();
+    /// std::thread::spawn(move||{*w+=1;}).join().unwrap();
+    /// assert_eq!(*s.read(),2);
+    /// ```
+    pub fn write_owned(&self)->OwnedGuard<T>
+    {
+        self.writelock(None);
+        self.released(LockType::Write,held::me());
+        OwnedGuard{
+            cura:self.clone(),
+            taken:Taken::unowned(),
+        }
+    }
+    ///
+    /// readlock a 'Cura' like read() but report if a writer
+    /// panicked while holding the lock. read() and write() just
+    /// ignore poisoning, use these to opt in
+    /// ```
+    /// use cura::Cura;
+    /// let s=Cura::new(1);
+    /// let c=s.clone();
+    /// let _=std::thread::spawn(move||{
+    ///     let _w=c.write();
+    ///     panic!("oops");
+    /// }).join();
+    /// assert!(s.read_checked().is_err());
+    /// assert_eq!(*s.read(),1); //still readable the usual way
+    /// s.clear_poison();
+    /// assert!(s.read_checked().is_ok());
+    /// ```
+    pub fn read_checked(&self)->LockResult<ReadGuard<'_,T>>
+    {
+        let guard=self.read();
+        if self.is_poisoned()
+        {
+            Err(PoisonError::new(guard))
+        }else{
+            Ok(guard)
+        }
+    }
+    ///
+    /// writelock a 'Cura' like write() but report if a writer
+    /// panicked while holding the lock
+    ///
+    pub fn write_checked(&self)->LockResult<Guard<'_,T>>
+    {
+        let guard=self.write();
+        if self.is_poisoned()
+        {
+            Err(PoisonError::new(guard))
+        }else{
+            Ok(guard)
+        }
+    }
+    ///
+    /// check whether some writer panicked while holding the lock
+    ///
+    pub fn is_poisoned(&self)->bool
+    {
+        self.state().load(SeqCst)&POISONED!=0
+    }
+    ///
+    /// forget that the lock was poisoned, presumably after
+    /// checking that the value is fine
+    ///
+    pub fn clear_poison(&self)
+    {
+        self.state().fetch_and(!POISONED,SeqCst);
+    }
+    ///
+    /// allow threads that are already reading this Cura to take
+    /// more readlocks on it even if writers are waiting. without
+    /// this a nested read() that would have to wait panics, since
+    /// waiting behind a writer that waits for us never ends
+    /// ```
+    /// use cura::Cura;
+    /// let s=Cura::new(1);
+    /// s.set_reentrant(true);
+    /// let a=s.read();
+    /// let b=s.read();
+    /// assert_eq!(*a+*b,2);
+    /// ```
+    pub fn set_reentrant(&self,reentrant:bool)
+    {
+        if reentrant
+        {
+            self.state().fetch_or(REENTRANT,SeqCst);
+        }else{
+            self.state().fetch_and(!REENTRANT,SeqCst);
+        }
+    }
+    ///
+    /// check whether nested readlocks are allowed
+    ///
+    pub fn is_reentrant(&self)->bool
+    {
+        self.state().load(SeqCst)&REENTRANT!=0
+    }
+    ///
+    /// set how this Cura spins before parking a thread, None
+    /// to follow default_spin_policy()
+    ///
+    pub fn set_spin_policy(&self,policy:Option<SpinPolicy>)
+    {
+        let bits=policy.map_or(0,|p|p.encode(SPINCOUNTBITS));
+        let _=self.data().head.config.fetch_update(Relaxed,Relaxed,|x|Some(x&!SPINBITS|bits));
+    }
+    ///
+    /// how this Cura spins before parking a thread
+    ///
+    pub fn spin_policy(&self)->SpinPolicy
+    {
+        let bits=self.data().head.config.load(Relaxed)&SPINBITS;
+        SpinPolicy::decode(bits,SPINCOUNTBITS).unwrap_or_else(default_spin_policy)
+    }
+    ///
+    /// the policy this Cura was made with
+    ///
+    pub fn policy(&self)->Policy
+    {
+        Policy::from_bits(self.data().head.config.load(Relaxed))
+    }
+    ///
+    /// readlock a 'Cura' so that the lock can later be upgraded
+    /// into a writelock without letting any writer in between.
+    /// only one upgradable reader is let in at a time, but plain
+    /// readers can keep reading alongside it
+    /// ```
+    /// use cura::Cura;
+    /// let s=Cura::new(1);
+    /// let r=s.upgradable_read();
+    /// assert_eq!(*s.read(),1); //other readers are fine
+    /// if *r==1 {
+    ///     let mut w=r.upgrade();
+    ///     *w=2;
+    /// }
+    /// assert_eq!(*s.read(),2);
+    /// ```
+    pub fn upgradable_read(&self)->UpgradableReadGuard<'_,T>
+    {
+        self.upgradablelock();
+        UpgradableReadGuard{
+            cura:self,
+            taken:Taken::now(),
+        }
+    }
+    ///
+    /// attempt to take an upgradable readlock once without
+    /// spinning or queueing
+    ///
+    pub fn try_upgradable_read(&self)->Option<UpgradableReadGuard<'_,T>>
+    {
+        if self.may_try(LockType::Upgradable,false) && self.try_upgradablelock()
+        {
+            Some(UpgradableReadGuard{
+                cura:self,
+                taken:Taken::now(),
+            })
+        }else{
+            None
+        }
+    }
+    ///
+    /// readlock a 'Cura' like read() but give up and return None
+    /// if the lock could not be acquired within the given time
+    /// ```
+    /// use cura::Cura;
+    /// use std::time::Duration;
+    /// let s=Cura::new(1);
+    /// let w=s.write();
+    /// assert!(s.read_timeout(Duration::from_millis(10)).is_none());
+    /// drop(w);
+    /// assert_eq!(*s.read_timeout(Duration::from_millis(10)).unwrap(),1);
+    /// ```
+    pub fn read_timeout(&self,timeout:Duration)->Option<ReadGuard<'_,T>>
+    {
+        match Instant::now().checked_add(timeout)
+        {
+            Some(deadline)=>self.read_until(deadline),
+            None=>Some(self.read()), //  practically forever
+        }
+    }
+    ///
+    /// readlock a 'Cura' like read() but give up and return None
+    /// if the lock could not be acquired before the deadline
+    ///
+    pub fn read_until(&self,deadline:Instant)->Option<ReadGuard<'_,T>>
+    {
+        if self.readlock(Some(deadline))
+        {
+            Some(ReadGuard{
+                cura:self,
+                taken:Taken::now(),
+            })
+        }else{
+            None
+        }
+    }
+    ///
+    /// writelock a 'Cura' like write() but give up and return None
+    /// if the lock could not be acquired within the given time
+    /// ```
+    /// use cura::Cura;
+    /// use std::time::Duration;
+    /// let s=Cura::new(1);
+    /// let r=s.read();
+    /// assert!(s.write_timeout(Duration::from_millis(10)).is_none());
+    /// drop(r);
+    /// *s.write_timeout(Duration::from_millis(10)).unwrap()=2;
+    /// ```
+    pub fn write_timeout(&self,timeout:Duration)->Option<Guard<'_,T>>
+    {
+        match Instant::now().checked_add(timeout)
+        {
+            Some(deadline)=>self.write_until(deadline),
+            None=>Some(self.write()), //  practically forever
+        }
+    }
+    ///
+    /// writelock a 'Cura' like write() but give up and return None
+    /// if the lock could not be acquired before the deadline
+    ///
+    pub fn write_until(&self,deadline:Instant)->Option<Guard<'_,T>>
+    {
+        if self.writelock(Some(deadline))
+        {
+            Some(Guard{
+                cura:self,
+                taken:Taken::now(),
+            })
+        }else{
+            None
+        }
+    }
+    ///
+    /// transparently take a writelock, attempt to mutate the value
+    /// and then release the lock
     /// ```
     /// use cura::Cura;
     /// let t=Cura::new(1);
@@ -317,345 +1307,2706 @@ impl<T:  Sync + Send + ?Sized> Cura<T> {
             },
         }
     }
-    //TBD method to swap values with options
-}
-///
-/// cura private stuff
-///
-impl<T:  Sync + Send + ?Sized> Cura<T> {
     ///
-    /// util to get accesss to curadata
+    /// transparently take a writelock, run the closure on the value
+    /// and return whatever it returns. unlike alter() the closure
+    /// can capture its environment
+    /// ```
+    /// use cura::Cura;
+    /// let t=Cura::new(vec![1,2]);
+    /// let add=3;
+    /// let len=t.update(|v|{
+    ///     v.push(add);
+    ///     v.len()
+    /// });
+    /// assert_eq!(len,3);
+    /// ```
+    pub fn update<R>(&self,f:impl FnOnce(&mut T)->R)->R
+    {
+        let mut lock=self.write();
+        f(&mut *lock)
+    }
+    ///
+    /// like update() but for closures that can fail, the error is
+    /// passed through as is. note that whatever the closure changed
+    /// before failing stays changed
+    /// ```
+    /// use cura::Cura;
+    /// let t=Cura::new(1);
+    /// let res:Result<i32,&str>=t.try_update(|x|{
+    ///     if *x>0 {
+    ///         *x-=1;
+    ///         Ok(*x)
+    ///     }else{
+    ///         Err("already zero")
+    ///     }
+    /// });
+    /// assert_eq!(res,Ok(0));
+    /// assert!(t.try_update(|x|if *x>0 {Ok(())}else{Err(())}).is_err());
+    /// ```
+    pub fn try_update<R,E>(&self,f:impl FnOnce(&mut T)->Result<R,E>)->Result<R,E>
+    {
+        self.update(f)
+    }
+    ///
+    /// transparently take a readlock, run the closure on the value
+    /// and return whatever it returns
+    /// ```
+    /// use cura::Cura;
+    /// let t=Cura::new(vec![1,2,3]);
+    /// let wanted=2;
+    /// assert!(t.with(|v|v.contains(&wanted)));
+    /// ```
+    pub fn with<R>(&self,f:impl FnOnce(&T)->R)->R
+    {
+        let lock=self.read();
+        f(&*lock)
+    }
+    ///
+    /// create a weak reference to this Cura
+    /// ```
+    /// use cura::{Cura,WeakCura};
+    /// struct Node{
+    ///     parent:Option<WeakCura<Node>>,
+    ///     children:Vec<Cura<Node>>,
+    /// }
+    /// let root=Cura::new(Node{parent:None,children:Vec::new()});
+    /// let child=Cura::new(Node{parent:Some(root.downgrade()),children:Vec::new()});
+    /// root.write().children.push(child.clone());
+    /// let parent=child.read().parent.as_ref().unwrap().upgrade();
+    /// assert!(parent.is_some());
+    /// ```
+    pub fn downgrade(&self)->WeakCura<T>
+    {
+        self.data().head.add_weak();
+        WeakCura{
+            ptr:self.ptr,
+            phantom:PhantomData,
+        }
+    }
+    ///
+    /// what the lock is held for right now. like the other counts
+    /// here this is only a snapshot, by the time you look at it
+    /// things may well have changed unless you hold the lock
+    /// ```
+    /// use cura::{Cura,LockState};
+    /// let s=Cura::new(1);
+    /// assert_eq!(s.lock_state(),LockState::Free);
+    /// let w=s.write();
+    /// assert_eq!(s.lock_state(),LockState::Write);
+    /// ```
+    pub fn lock_state(&self)->LockState
+    {
+        let lock=self.state().load(SeqCst);
+        if lock&WRITER!=0
+        {
+            LockState::Write
+        }else if lock&READERS!=0{
+            LockState::Read(lock/READER)
+        }else{
+            LockState::Free
+        }
+    }
+    ///
+    /// whether the lock is held for anything, a racy snapshot
+    ///
+    pub fn is_locked(&self)->bool
+    {
+        self.lock_state()!=LockState::Free
+    }
+    ///
+    /// number of threads and tasks waiting for the lock, those
+    /// just woken to go for it included. a racy snapshot
+    ///
+    pub fn waiter_count(&self)->usize
+    {
+        let qdata=self.lock_queue();
+        let count=qdata.count as usize+qdata.wakeups;
+        self.unlock_queue(qdata);
+        count
+    }
+    ///
+    /// number of Curae pointing to this value, a racy snapshot
+    ///
+    pub fn strong_count(&self)->usize
+    {
+        self.data().head.count.load(Acquire)
+    }
+    ///
+    /// number of WeakCurae pointing to this value, a racy snapshot
+    ///
+    pub fn weak_count(&self)->usize
+    {
+        self.data().head.weakcount.load(Acquire) as usize-1
+    }
+    ///
+    /// whether the two point to the same value
+    /// ```
+    /// use cura::Cura;
+    /// let a=Cura::new(1);
+    /// assert!(a.ptr_eq(&a.clone()));
+    /// assert!(!a.ptr_eq(&Cura::new(1)));
+    /// ```
+    pub fn ptr_eq(&self,other:&Cura<T>)->bool
+    {
+        std::ptr::eq(self.ptr.as_ptr() as *const u8,other.ptr.as_ptr() as *const u8)
+    }
+    ///
+    /// pointer to the value, without locking anything. it stays
+    /// valid as long as some Cura does, but reading or writing
+    /// through it is up to you to synchronize
+    ///
+    pub fn as_ptr(&self)->*const T
+    {
+        self.data().data.get()
+    }
+}
+///
+/// cura private stuff
+///
+impl<T:  Sync + Send + ?Sized> Cura<T> {
+    ///
+    /// util to get accesss to curadata
+    ///
+    fn data(&self) -> &CuraData<T> {
+        unsafe { self.ptr.as_ref() }
+    }
+    ///
+    /// the word with the lock and queue state
+    ///
+    fn state(&self)->&AtomicUsize
+    {
+        &self.data().head.state
+    }
+    ///
+    /// address of the shared data, identifies the lock
+    ///
+    fn addr(&self)->usize
+    {
+        self.ptr.as_ptr() as *const u8 as usize
+    }
+    ///
+    /// bookkeeping for a lock we just got
+    ///
+    fn acquired(&self,t:LockType)
+    {
+        deadlock::acquired(self.addr());
+        held::acquired(self.addr(),t);
+    }
+    ///
+    /// bookkeeping for a lock we are about to release, which owner
+    /// is registered as holding if anyone. that is not always us,
+    /// guards can be sent to other threads
+    ///
+    fn released(&self,t:LockType,owner:Option<ThreadId>)
+    {
+        deadlock::released(self.addr(),owner);
+        held::released(self.addr(),t,owner);
+    }
+    ///
+    /// a forgotten guard left the lock held for good as the last
+    /// Cura went away, and the address may soon be some other
+    /// Cura's. whoever was registered as holding it no longer is
+    ///
+    fn leaked(&self)
+    {
+        deadlock::freed(self.addr());
+        held::freed(self.addr());
+    }
+    ///
+    /// spin and queue until we get a readlock or the deadline
+    /// passes, returns true if we got the lock
+    ///
+    fn readlock(&self,deadline:Option<Instant>)->bool
+    {
+        //  how many times have we looped here...
+        let mut loops=0;
+        let spin=self.spin_policy();
+        //  did we just come off the front of the queue
+        let mut woken=false;
+        loop{
+            let tried=self.may_try(LockType::Read,woken);
+            let got=tried && self.try_readlock();
+            if woken
+            {
+                self.tried_wakeup();
+            }
+            if got
+            {
+                /*    x readers,including us*/
+                //  let everyone else in from the queue
+                if self.parked()
+                {
+                    self.wakereader();
+                }
+                return true;
+            }
+            //  we already read this one, dont wait behind others
+            //  for something we are holding ourselves
+            if self.is_reentrant() && self.try_reentrant_readlock()
+            {
+                return true;
+            }
+            /*   its probably writelocked,so we will spin*/
+            if self.should_queue(spin.done(loops),tried,woken) && !self.reading_unopposed()
+            {
+                match self.enqueue(LockType::Read,woken,deadline) {
+                    Wakeup::TimedOut=>{return false;},
+                    Wakeup::Handed=>{
+                        self.acquired(LockType::Read);
+                        return true;
+                    },
+                    Wakeup::Woken=>{},
+                }
+                woken=true;
+                loops=0;
+            }else{
+                woken=false;
+                spin.wait(loops);
+                loops+=1;
+            }
+        }
+    }
+    ///
+    /// spin and queue until we get a writelock or the deadline
+    /// passes, returns true if we got the lock
+    ///
+    fn writelock(&self,deadline:Option<Instant>)->bool
+    {
+        let mut loops=0;
+        let spin=self.spin_policy();
+        let mut woken=false;
+        loop{
+            let tried=self.may_try(LockType::Write,woken);
+            let got=tried && self.try_writelock();
+            if woken
+            {
+                self.tried_wakeup();
+            }
+            if got
+            {
+                /*    should be just us , writing*/
+                return true;
+            }
+            /*   its write/readlocked,so we will spin*/
+            if self.should_queue(spin.done(loops),tried,woken)
+            {
+                match self.enqueue(LockType::Write,woken,deadline) {
+                    Wakeup::TimedOut=>{return false;},
+                    Wakeup::Handed=>{
+                        self.acquired(LockType::Write);
+                        return true;
+                    },
+                    Wakeup::Woken=>{},
+                }
+                woken=true;
+                loops=0;
+            }else{
+                woken=false;
+                spin.wait(loops);
+                loops+=1;
+            }
+        }
+    }
+    ///
+    /// does our policy let us try for the lock right now, or
+    /// should we get behind whoever is waiting. woken tells if
+    /// we just came off the front of the queue
+    ///
+    fn may_try(&self,t:LockType,woken:bool)->bool
+    {
+        match (self.policy(),t) {
+            (Policy::Barging,_)=>true,
+            (Policy::Fifo,LockType::Read)=>woken || !self.parked() || self.reading_unopposed(),
+            (Policy::Fifo,_)=>woken || !self.parked(),
+            (Policy::WriterPreferring,LockType::Write)=>true,
+            (Policy::WriterPreferring,_)=>self.state().load(SeqCst)&WRITERPARKED==0,
+            (Policy::ReaderPreferring,LockType::Write)=>woken || !self.parked(),
+            (Policy::ReaderPreferring,_)=>true,
+        }
+    }
+    ///
+    /// whether this thread already reads the lock and no writer
+    /// or upgrader waits for it. whoever else is in the queue or
+    /// just woken out of it waits for the same thing we do, so
+    /// there is nobody to line up behind
+    ///
+    fn reading_unopposed(&self)->bool
+    {
+        let held=held::get(self.addr());
+        (held.reads>0 || held.upgradable>0)
+            && self.state().load(SeqCst)&(WRITERPARKED|UPGRADING)==0
+    }
+    ///
+    /// should we stop spinning and get into the queue
+    ///
+    fn should_queue(&self,spun:bool,tried:bool,woken:bool)->bool
+    {
+        match self.policy() {
+            Policy::Barging=>spun || self.parked(),
+            //  dont spin away our place in the queue
+            Policy::Fifo=>woken || !tried || spun,
+            _=>!tried || spun,
+        }
+    }
+    ///
+    /// check whether the lock looks obtainable for the given type
+    ///
+    fn lockable(&self,t:&LockType)->bool
+    {
+        let lock=self.state().load(SeqCst);
+        match t {
+            LockType::Read=>lock&(WRITER|UPGRADING)==0,
+            LockType::Write=>lock&(WRITER|READERS)==0,
+            LockType::Upgradable=>lock&(WRITER|UPGRADABLE)==0,
+            LockType::Upgrade=>lock&READERS==READER,
+        }
+    }
+    ///
+    /// single attempt at incrementing the readlock count
+    ///
+    fn try_readlock(&self)->bool
+    {
+        //TBD think through these memory orderings
+        let lock=self.state().fetch_update(
+                                    SeqCst,
+                                    SeqCst,
+                                    |x|{
+                                        //  a full reader count waits like a writelock
+                                        if x&(WRITER|UPGRADING)==0{
+                                            x.checked_add(READER)
+                                        }else{
+                                            None
+                                        }
+                                    });
+        if lock.is_ok()
+        {
+            self.acquired(LockType::Read);
+        }
+        lock.is_ok()
+    }
+    ///
+    /// take another readlock regardless of who is waiting, if
+    /// this thread is already reading
+    ///
+    fn try_reentrant_readlock(&self)->bool
+    {
+        let held=held::get(self.addr());
+        if held.reads==0 && held.upgradable==0
+        {
+            return false;
+        }
+        //  cant be writelocked since we are reading
+        let lock=self.state().fetch_update(SeqCst,SeqCst,|x|x.checked_add(READER));
+        if lock.is_ok()
+        {
+            self.acquired(LockType::Read);
+        }
+        lock.is_ok()
+    }
+    ///
+    /// single attempt at taking the upgradable readlock, there
+    /// can only be one of those at a time
+    ///
+    fn try_upgradablelock(&self)->bool
+    {
+        let lock=self.state().fetch_update(
+                                    SeqCst,
+                                    SeqCst,
+                                    |x|{
+                                        if x&(WRITER|UPGRADABLE)==0{
+                                            x.checked_add(UPGRADABLE+READER)
+                                        }else{
+                                            None
+                                        }
+                                    });
+        if lock.is_ok()
+        {
+            self.acquired(LockType::Upgradable);
+        }
+        lock.is_ok()
+    }
+    ///
+    /// single attempt at turning our upgradable readlock into
+    /// a writelock, only works if we are the only reader
+    ///
+    fn try_upgradelock(&self,taken:Taken)->bool
+    {
+        let lock=self.state().fetch_update(
+                                    SeqCst,
+                                    SeqCst,
+                                    |x|{
+                                        if x&READERS==READER{
+                                            Some(x&!(READERS|UPGRADABLE|UPGRADING)|WRITER)
+                                        }else{
+                                            None
+                                        }
+                                    });
+        if lock.is_ok()
+        {
+            self.upgraded(taken);
+        }
+        lock.is_ok()
+    }
+    ///
+    /// bookkeeping for an upgradable readlock taken as described
+    /// by taken that we just turned into a writelock
+    ///
+    fn upgraded(&self,taken:Taken)
+    {
+        self.released(LockType::Upgradable,taken.owner);
+        self.acquired(LockType::Write);
+    }
+    ///
+    /// spin and queue until we get the upgradable readlock
+    ///
+    fn upgradablelock(&self)
+    {
+        let mut loops=0;
+        let spin=self.spin_policy();
+        let mut woken=false;
+        loop{
+            let tried=self.may_try(LockType::Upgradable,woken);
+            let got=tried && self.try_upgradablelock();
+            if woken
+            {
+                self.tried_wakeup();
+            }
+            if got
+            {
+                if self.parked()
+                {
+                    self.wakereader();
+                }
+                return;
+            }
+            if self.should_queue(spin.done(loops),tried,woken)
+            {
+                if self.enqueue(LockType::Upgradable,woken,None)==Wakeup::Handed
+                {
+                    self.acquired(LockType::Upgradable);
+                    return;
+                }
+                woken=true;
+                loops=0;
+            }else{
+                woken=false;
+                spin.wait(loops);
+                loops+=1;
+            }
+        }
+    }
+    ///
+    /// turn our upgradable readlock into a writelock, keeping
+    /// new readers out while we wait for the old ones to leave
+    ///
+    fn upgradelock(&self,taken:Taken)
+    {
+        self.state().fetch_or(UPGRADING,SeqCst);
+        let mut loops=0;
+        let spin=self.spin_policy();
+        let mut woken=false;
+        loop{
+            let got=self.try_upgradelock(taken);
+            if woken
+            {
+                self.tried_wakeup();
+            }
+            if got
+            {
+                return;
+            }
+            //  we go to the front of the queue since nobody else
+            //  can get anywhere before we are done
+            if spin.done(loops)
+            {
+                match self.enqueue(LockType::Upgrade,false,None) {
+                    Wakeup::Handed=>{
+                        self.upgraded(taken);
+                        return;
+                    },
+                    w=>{woken=w==Wakeup::Woken;},
+                }
+                loops=0;
+            }else{
+                woken=false;
+                spin.wait(loops);
+                loops+=1;
+            }
+        }
+    }
+    ///
+    /// single attempt at taking the writelock
+    ///
+    fn try_writelock(&self)->bool
+    {
+        let lock=self.state().fetch_update(
+                                    SeqCst,
+                                    SeqCst,
+                                    |x|{
+                                        if x&(WRITER|READERS)==0{
+                                            Some(x|WRITER)
+                                        }else{
+                                            None
+                                        }
+                                    });
+        if lock.is_ok()
+        {
+            self.acquired(LockType::Write);
+        }
+        lock.is_ok()
+    }
+    ///
+    /// spin until we can acquire a lock on queue by setting the
+    /// QUEUELOCKED bit of its head pointer, returning a copy of
+    /// the queue for us to work on until unlock_queue()
+    ///
+    fn lock_queue(&self)->QueueData
+    {
+        let queue=&self.data().head.queue;
+        let mut loops=0;
+        loop{
+            let head=queue.load(Relaxed);
+            if head as usize&QUEUELOCKED==0
+            {
+                let locked=(head as *mut u8).wrapping_add(QUEUELOCKED) as *mut QueueLink;
+                if queue.compare_exchange_weak(head,locked,Acquire,Relaxed).is_ok()
+                {
+                    /*  locked successfully*/
+                    let mut qdata=QueueData{
+                        queue:head,
+                        endqueue:std::ptr::null_mut(),
+                        count:0,
+                        writers:0,
+                        wakeups:(self.state().load(Relaxed)&WAKEUPS)/WAKEUP,
+                        wake:Wakes::default(),
+                    };
+                    if !head.is_null()
+                    {
+                        unsafe{
+                            qdata.endqueue=(*head).endqueue;
+                            qdata.count=(*head).count;
+                            qdata.writers=(*head).writers;
+                        }
+                    }
+                    return qdata;
+                }
+            }
+            /*  it is already locked, so we spin. if that goes on
+                for long whoever has it is probably not running, so
+                let them instead of spinning away our timeslice*/
+            if loops>100
+            {
+                std::thread::yield_now();
+            }else{
+                loops+=1;
+                std::hint::spin_loop();
+            }
+        }
+    }
+    ///
+    /// put back the queue we got from lock_queue() and unlock it
+    /// by storing the head without the QUEUELOCKED bit. the parked
+    /// bits are updated before that so that lockers can tell
+    /// whether anyone waits without locking the queue. those
+    /// taken out of the queue meanwhile are woken after that
+    ///
+    fn unlock_queue(&self,qdata:QueueData)
+    {
+        if !qdata.queue.is_null()
+        {
+            unsafe{
+                (*qdata.queue).endqueue=qdata.endqueue;
+                (*qdata.queue).count=qdata.count;
+                (*qdata.queue).writers=qdata.writers;
+            }
+        }
+        let mut parked=qdata.wakeups*WAKEUP;
+        if qdata.count>0 || qdata.wakeups>0
+        {
+            parked|=PARKED;
+        }
+        if qdata.writers>0
+        {
+            parked|=WRITERPARKED;
+        }
+        let _=self.state().fetch_update(SeqCst,Relaxed,|x|{
+            Some(x&!(PARKED|WRITERPARKED|WAKEUPS)|parked)
+        });
+        self.data().head.queue.store(qdata.queue,Release);
+        qdata.wake.wake();
+    }
+    ///
+    /// whether anyone is in the queue or just woken out of it
+    ///
+    fn parked(&self)->bool
+    {
+        self.state().load(SeqCst)&PARKED!=0
+    }
+    ///
+    /// lock queue and insert ourselves to it and park
+    /// waiting for the time in the future when we are
+    /// unparked as the first in the queue. returns false
+    /// if the deadline passed first, in which case we are
+    /// no longer in the queue. retry is for those that just
+    /// came off the front and did not get the lock after all
+    ///
+    fn enqueue(&self,t:LockType,retry:bool,deadline:Option<Instant>)->Wakeup{
+
+        //  a timed wait ends by itself, so it cant deadlock
+        if deadline.is_none()
+        {
+            held::check(self.addr(),t);
+            deadlock::waiting(self.addr(),t==LockType::Upgrade);
+        }
+        let got=self.park_in_queue(t,retry,deadline);
+        if deadline.is_none()
+        {
+            deadlock::done_waiting();
+        }
+        got
+    }
+    ///
+    /// the actual queueing and parking for enqueue()
+    ///
+    fn park_in_queue(&self,t:LockType,retry:bool,deadline:Option<Instant>)->Wakeup{
+
+        //  our link lives right here, we dont return before
+        //  it is out of the queue
+        let mut link=QueueLink::new(t,Waiter::Thread(std::thread::current()));
+        let link:*mut QueueLink=&mut link;
+        self.join_queue(link,retry);
+
+        //  and park, ready to spin on return
+        loop{
+            let timedout=match deadline {
+                None=>{
+                    std::thread::park();
+                    false
+                },
+                Some(deadline)=>{
+                    let now=Instant::now();
+                    if now<deadline
+                    {
+                        std::thread::park_timeout(deadline-now);
+                    }
+                    Instant::now()>=deadline
+                },
+            };
+            let qdata=self.lock_queue();
+            let woken=self.take_wakeup(link);
+            self.unlock_queue(qdata);
+            if let Some(woken)=woken
+            {
+                self.settled(link);
+                return woken;
+            }
+            //  woken by something else, or not at all
+            if timedout
+            {
+                return self.leave_queue(link);
+            }
+        }
+    }
+    ///
+    /// if whoever owns the link has been taken out of the queue
+    /// return how, after which the link is theirs again once it
+    /// is settled(). None if it is still queued. assumes queue is
+    /// already locked by us
+    ///
+    fn take_wakeup(&self,link:*mut QueueLink)->Option<Wakeup>
+    {
+        unsafe{
+            if !(*link).woken
+            {
+                return None;
+            }
+            if (*link).handed
+            {
+                Some(Wakeup::Handed)
+            }else{
+                Some(Wakeup::Woken)
+            }
+        }
+    }
+    ///
+    /// wait for whoever took the link out of the queue to be done
+    /// with it, which only takes a moment after the queue is
+    /// unlocked. not to be done with the queue locked
+    ///
+    fn settled(&self,link:*mut QueueLink)
+    {
+        unsafe{
+            while !(*link).settled.load(Acquire)
+            {
+                std::hint::spin_loop();
+            }
+        }
+    }
+    ///
+    /// a woken waiter had its go at the lock, so the next ones
+    /// can be woken again
+    ///
+    fn tried_wakeup(&self)
+    {
+        let mut qdata=self.lock_queue();
+        qdata.wakeups-=1;
+        self.wakenext(&mut qdata,None);
+        self.unlock_queue(qdata);
+    }
+    ///
+    /// lock queue and insert a waiter's link into it where the
+    /// policy says it belongs. the link has to stay put until
+    /// take_wakeup() or leave_queue() is done with it
+    ///
+    fn join_queue(&self,link:*mut QueueLink,retry:bool)
+    {
+        use LockType::*;
+        let t=unsafe{(*link).lock};
+        //  lock and increment queue size
+        let mut qdata=self.lock_queue();
+        qdata.inc(t);
+
+        //  insert ourselves into queue
+        match (self.policy(),t) {
+            //  nobody can get anywhere before an upgrade is done
+            (_,Upgrade)=>qdata.enqueue_before(link,|_|true),
+            //  back to where we were, which was the front
+            (Policy::Fifo,_) if retry=>
+                qdata.enqueue_before(link,|l|l!=Upgrade),
+            (Policy::WriterPreferring,Write)=>
+                qdata.enqueue_before(link,|l|l==Read || l==Upgradable),
+            (Policy::ReaderPreferring,Read|Upgradable)=>
+                qdata.enqueue_before(link,|l|l==Write),
+            _=>qdata.enqueue(link),
+        }
+        //  the lock may have been released after we failed to get it
+        //  but before we got into the queue, so make sure someone
+        //  in front will retry instead of everyone sleeping forever.
+        //  releasers only look at the queue if it is parked, so that
+        //  has to show before we check, or a release in between
+        //  would see neither us nor need to wake anyone
+        self.state().fetch_or(PARKED,SeqCst);
+        self.wakenext(&mut qdata,None);
+        //  unlock queue for others to modify and see
+        self.unlock_queue(qdata);
+    }
+    ///
+    /// give up our place in the queue, if we were already woken
+    /// we have eaten a wakeup meant for whoever is next. if the
+    /// lock was handed to us already we have it after all, and
+    /// Handed is returned instead of TimedOut
+    ///
+    fn leave_queue(&self,link:*mut QueueLink)->Wakeup
+    {
+        let mut qdata=self.lock_queue();
+        let woken=self.take_wakeup(link);
+        let left=match woken {
+            Some(Wakeup::Handed)=>Wakeup::Handed,
+            Some(_)=>{
+                qdata.wakeups-=1;
+                self.wakenext(&mut qdata,None);
+                Wakeup::TimedOut
+            },
+            None=>{
+                qdata.remove(link);
+                qdata.dec(unsafe{(*link).lock});
+                Wakeup::TimedOut
+            },
+        };
+        self.unlock_queue(qdata);
+        if woken.is_some()
+        {
+            self.settled(link);
+        }
+        left
+    }
+    ///
+    /// check on behalf of an async task whether it has been woken,
+    /// in which case it is told how. otherwise remember the
+    /// latest waker to use
+    ///
+    fn poll_queue(&self,link:*mut QueueLink,waker:&Waker)->Option<Wakeup>
+    {
+        //  cloning and dropping wakers is user code too, keep it
+        //  out of the queue lock
+        let mut waiter=Some(Waiter::Task(waker.clone()));
+        let qdata=self.lock_queue();
+        let woken=self.take_wakeup(link);
+        if woken.is_none()
+        {
+            unsafe{
+                match &(*link).waiter {
+                    Some(Waiter::Task(w)) if w.will_wake(waker)=>{},
+                    _=>std::mem::swap(&mut (*link).waiter,&mut waiter),
+                }
+            }
+        }
+        self.unlock_queue(qdata);
+        drop(waiter);
+        if woken.is_some()
+        {
+            self.settled(link);
+        }
+        woken
+    }
+    ///
+    /// one step of taking a lock for an async task, queueing
+    /// it with its waker if the lock could not be had. the link
+    /// is kept in the pinned future and is Some while queued
+    ///
+    fn poll_lock(&self,link:&mut Option<QueueLink>,t:LockType,cx:&mut Context<'_>)->Poll<()>
+    {
+        let woken=link.is_some();
+        if let Some(l)=link
+        {
+            let woken=self.poll_queue(l,cx.waker());
+            if woken.is_none()
+            {
+                return Poll::Pending;
+            }
+            *link=None;
+            if woken==Some(Wakeup::Handed)
+            {
+                self.acquired(t);
+                self.released(t,held::me());
+                return Poll::Ready(());
+            }
+        }
+        //  no spinning here, that would just block the executor
+        let locked=self.may_try(t,woken) && match t {
+            LockType::Read=>self.try_readlock(),
+            LockType::Write=>self.try_writelock(),
+            _=>unreachable!("only reads and writes are async"),
+        };
+        if woken
+        {
+            self.tried_wakeup();
+        }
+        if locked
+        {
+            //  tasks hop between threads, so dont tie the lock to this one
+            self.released(t,held::me());
+            if t==LockType::Read && self.parked()
+            {
+                self.wakereader();
+            }
+            return Poll::Ready(());
+        }
+        let l=link.insert(QueueLink::new(t,Waiter::Task(cx.waker().clone())));
+        self.join_queue(l,woken);
+        Poll::Pending
+    }
+    ///
+    /// if the first in the queue could have the lock now take it
+    /// out and wake it, along with all the readers right behind
+    /// if it is a reader itself. nobody is woken for a lock they
+    /// cant have, which would only have them queue up again, nor
+    /// while those woken before have not tried yet, so they dont
+    /// have to race the ones that queued after them. there is room
+    /// to count MAXWAKEUPS of those, the rest of a long run of
+    /// readers is woken once they have tried.
+    /// with handoff set we are releasing a lock of that type and
+    /// hand it straight over to those we take out instead, so
+    /// nobody can barge in between. returns whether anyone was
+    /// taken out, if not we still hold the lock we meant to hand.
+    /// assumes queue is already locked by us
+    ///
+    fn wakenext(&self,qdata:&mut QueueData,handoff:Option<LockType>)->bool
+    {
+        use LockType::*;
+        unsafe{
+            let first=qdata.queue;
+            if first.is_null() || qdata.wakeups>0
+            {
+                return false;
+            }
+            let next=(*first).lock;
+            let mut count=1;
+            let mut link=(*first).next;
+            while next==Read && !link.is_null() && (*link).lock==Read && count<MAXWAKEUPS
+            {
+                count+=1;
+                link=(*link).next;
+            }
+            match handoff {
+                None if !self.lockable(&next)=>{return false;},
+                None=>{},
+                Some(t)=>{
+                    let lock=self.state().fetch_update(SeqCst,SeqCst,|x|{
+                        //  the state as if we had let go, then as if they got it
+                        let x=match t {
+                            Write=>x&!WRITER,
+                            Read=>x-READER,
+                            Upgradable=>x-(UPGRADABLE+READER),
+                            Upgrade=>unreachable!("upgrades are not released"),
+                        };
+                        match next {
+                            Read if x&(WRITER|UPGRADING)==0=>x.checked_add(count*READER),
+                            Write if x&(WRITER|READERS)==0=>Some(x|WRITER),
+                            Upgradable if x&(WRITER|UPGRADABLE)==0=>x.checked_add(UPGRADABLE+READER),
+                            Upgrade if x&READERS==READER=>Some(x&!(READERS|UPGRADABLE|UPGRADING)|WRITER),
+                            _=>None,
+                        }
+                    });
+                    if lock.is_err()
+                    {
+                        return false;
+                    }
+                },
+            }
+            for _ in 0..count
+            {
+                let link=qdata.pop();
+                qdata.dec((*link).lock);
+                //  a handed lock is theirs already, no need to try it
+                if handoff.is_none()
+                {
+                    qdata.wakeups+=1;
+                }
+                (*link).woken=true;
+                (*link).handed=handoff.is_some();
+                qdata.wake.push(link);
+            }
+            true
+        }
+    }
+    ///
+    /// hand a lock of type t that we are releasing over to the
+    /// queue through wakenext(), returns false if nobody there
+    /// could have it, in which case we still hold it
+    ///
+    fn handoff(&self,t:LockType)->bool
+    {
+        if !self.parked()
+        {
+            return false;
+        }
+        let mut qdata=self.lock_queue();
+        let handed=self.wakenext(&mut qdata,Some(t));
+        self.unlock_queue(qdata);
+        handed
+    }
+    ///
+    /// release a lock of type t taken as described by taken,
+    /// handing it over to whoever is first in the queue if we can
+    ///
+    fn unlock_fair(&self,t:LockType,taken:Taken)
+    {
+        if t==LockType::Write
+        {
+            self.poison(taken);
+        }
+        if self.handoff(t)
+        {
+            self.released(t,taken.owner);
+            return;
+        }
+        match t {
+            LockType::Read=>self.unreadlock(taken),
+            LockType::Write=>self.unwritelock(taken),
+            LockType::Upgradable=>self.unupgradablelock(taken),
+            LockType::Upgrade=>unreachable!("upgrades are not released"),
+        }
+    }
+    ///
+    /// hand the lock over to the queue if anyone there can take
+    /// it, and get back in line for it ourselves. returns how
+    /// the lock we end up holding was taken
+    ///
+    fn bump(&self,t:LockType,taken:Taken)->Taken
+    {
+        if t==LockType::Write
+        {
+            self.poison(taken);
+        }
+        if !self.handoff(t)
+        {
+            return taken;
+        }
+        self.released(t,taken.owner);
+        match t {
+            LockType::Read=>{self.readlock(None);},
+            LockType::Write=>{self.writelock(None);},
+            _=>unreachable!("only reads and writes are bumped"),
+        }
+        Taken::now()
+    }
+    ///
+    /// wake readers in front of queue
+    ///
+    fn wakereader(&self)
+    {
+        let mut qdata=self.lock_queue();
+        let first=qdata.queue;
+        if !first.is_null() && unsafe{
+                (*first).lock==LockType::Read ||
+                (*first).lock==LockType::Upgradable}
+        {
+            self.wakenext(&mut qdata,None);
+        }
+        self.unlock_queue(qdata);
+    }
+    ///
+    /// mark the lock poisoned if a panic started while we held
+    /// it for writing, as whatever we were doing was probably left
+    /// half done. if the thread was already panicking when the
+    /// lock was taken, like in a drop() while unwinding, the panic
+    /// has nothing to do with it
+    ///
+    fn poison(&self,taken:Taken)
+    {
+        if !taken.panicking && std::thread::panicking()
+        {
+            self.state().fetch_or(POISONED,SeqCst);
+        }
+    }
+    ///
+    /// release write lock taken as described by taken
+    ///
+    fn unwritelock(&self,taken:Taken)
+    {
+        self.poison(taken);
+        self.released(LockType::Write,taken.owner);
+        let lock=self.state().fetch_and(!WRITER,SeqCst);
+        if lock&WRITER==0
+        {
+            panic!("was supposed to be locked but was {:#x}",lock);
+        }
+        if self.parked()
+        {
+            let mut qdata=self.lock_queue();
+            self.wakenext(&mut qdata,None);
+            self.unlock_queue(qdata);
+        }
+    }
+    ///
+    /// decrement number of readlocks held
+    ///
+    fn unreadlock(&self,taken:Taken)
+    {
+        self.released(LockType::Read,taken.owner);
+        let lock=self.state().fetch_sub(READER,SeqCst);
+        if lock&READERS==0
+        {
+            panic!("was supposed to be readlocked but was {:#x}",lock);
+        }
+        if self.parked()
+        {
+            let mut qdata=self.lock_queue();
+            self.wakenext(&mut qdata,None);
+            self.unlock_queue(qdata);
+        }
+    }
+    ///
+    /// turn a write lock into a single read lock and let the
+    /// readers waiting in front of the queue in
+    ///
+    fn downgradelock(&self,taken:Taken)
+    {
+        let lock=self.state().fetch_add(READER-WRITER,SeqCst);
+        if lock&WRITER==0
+        {
+            panic!("was supposed to be locked but was {:#x}",lock);
+        }
+        self.released(LockType::Write,taken.owner);
+        self.acquired(LockType::Read);
+        if self.parked()
+        {
+            self.wakereader();
+        }
+    }
+    ///
+    /// release upgradable read lock
+    ///
+    fn unupgradablelock(&self,taken:Taken)
+    {
+        self.released(LockType::Upgradable,taken.owner);
+        let lock=self.state().fetch_sub(UPGRADABLE+READER,SeqCst);
+        if lock&UPGRADABLE==0
+        {
+            panic!("was supposed to be upgradable readlocked but was {:#x}",lock);
+        }
+        if self.parked()
+        {
+            let mut qdata=self.lock_queue();
+            self.wakenext(&mut qdata,None);
+            self.unlock_queue(qdata);
+        }
+    }
+}
+
+/**
+ *  debug and display never wait for the lock, so they are safe to use
+ *  while holding it or while someone else does. if the value can't be
+ *  readlocked right away it shows as <locked>
+ */
+impl<T: Sync + Send + ?Sized + std::fmt::Debug> std::fmt::Debug for Cura<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        //  before we lock it ourselves
+        let state=self.lock_state();
+        let waiters=self.waiter_count();
+        let alternate=f.alternate();
+        let mut d=f.debug_struct("Cura");
+        let guard=self.try_readlock().then(||ReadGuard{cura:self,taken:Taken::now()});
+        match &guard {
+            Some(guard)=>d.field("data",&&**guard),
+            None=>d.field("data",&format_args!("<locked>")),
+        };
+        d.field("state",&state);
+        d.field("waiters",&waiters);
+        if alternate
+        {
+            d.field("poisoned",&self.is_poisoned());
+            d.field("policy",&self.policy());
+            d.field("strong",&self.strong_count());
+            d.field("weak",&self.weak_count());
+        }
+        d.finish()
+    }
+}
+impl<T: Sync + Send + ?Sized + std::fmt::Display> std::fmt::Display for Cura<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.try_readlock()
+        {
+            std::fmt::Display::fmt(&*ReadGuard{cura:self,taken:Taken::now()}, f)
+        }else{
+            f.write_str("<locked>")
+        }
+    }
+}
+impl<T:Sync+Send> From<T> for Cura<T> {
+    fn from(t:T)->Self {
+        Cura::new(t)
+    }
+}
+impl<T:Sync+Send+?Sized> From<Box<T>> for Cura<T> {
+    fn from(v:Box<T>)->Self {
+        Cura::from_box(v)
+    }
+}
+/**
+ * implement equality and ordering. if you mutate this value, you will get weird
+ * behavior , but it is what it is. use ByAddress for that.
+ *
+ * both are readlocked in address order like lock_all() does, so comparing
+ * a with b while another thread compares b with a cant deadlock. a Cura is
+ * always equal to itself without looking, even if its value is not, like
+ * a NaN
+ */
+impl<T: Sync + Send + ?Sized + PartialEq> PartialEq for Cura<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.ptr_eq(other) ||
+            lock_all((ReadLock(self),ReadLock(other)),|(a,b)|*a == *b)
+    }
+}
+impl<T: Sync + Send + ?Sized + Eq> Eq for Cura<T> {}
+impl<T: Sync + Send + ?Sized + PartialOrd> PartialOrd for Cura<T> {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        if self.ptr_eq(other)
+        {
+            return Some(std::cmp::Ordering::Equal);
+        }
+        lock_all((ReadLock(self),ReadLock(other)),|(a,b)|(*a).partial_cmp(&*b))
+    }
+}
+impl<T: Sync + Send + ?Sized + Ord> Ord for Cura<T> {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        if self.ptr_eq(other)
+        {
+            return std::cmp::Ordering::Equal;
+        }
+        lock_all((ReadLock(self),ReadLock(other)),|(a,b)|(*a).cmp(&*b))
+    }
+}
+///
+/// compares and hashes a Cura by which value it points to instead of
+/// what that value is, so it never locks anything and stays put as a
+/// key in a HashMap or a BTreeMap however the value changes
+/// ```
+/// use cura::{Cura,ByAddress};
+/// use std::collections::HashSet;
+/// let a=Cura::new(1);
+/// let b=Cura::new(1);
+/// let mut set=HashSet::new();
+/// set.insert(ByAddress(a.clone()));
+/// *a.write()=2;
+/// assert!(set.contains(&ByAddress(a.clone())));
+/// assert!(!set.contains(&ByAddress(b)));
+/// ```
+#[derive(Clone,Debug)]
+pub struct ByAddress<P>(pub P);
+impl<P> Deref for ByAddress<P> {
+    type Target = P;
+    fn deref(&self) -> &P {
+        &self.0
+    }
+}
+impl<T: Sync + Send + ?Sized> PartialEq for ByAddress<Cura<T>> {
+    fn eq(&self, other: &Self) -> bool {
+        self.0.ptr_eq(&other.0)
+    }
+}
+impl<T: Sync + Send + ?Sized> Eq for ByAddress<Cura<T>> {}
+impl<T: Sync + Send + ?Sized> PartialOrd for ByAddress<Cura<T>> {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+impl<T: Sync + Send + ?Sized> Ord for ByAddress<Cura<T>> {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        self.0.addr().cmp(&other.0.addr())
+    }
+}
+impl<T: Sync + Send + ?Sized> std::hash::Hash for ByAddress<Cura<T>> {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.0.addr().hash(state);
+    }
+}
+/**
+ *  hash by read-locking and hashing the value, this is not super efficient but
+ *  it ensures consistency while hashing
+ *
+ *  naturally , if you use this as a hash key and mutate the value while it is in the hash,
+ *  you will get weird behavior
+ */
+impl<T: Sync + Send + ?Sized + std::hash::Hash> std::hash::Hash for Cura<T> {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        (*self.read()).hash(state);
+    }
+}
+
+/**
+ *  let Cura<T> turn into Cura<dyn Trait> by itself, on nightly only
+ */
+#[cfg(feature="coerce_unsized")]
+impl<T,U> std::ops::CoerceUnsized<Cura<U>> for Cura<T>
+    where T:Sync+Send+?Sized+std::marker::Unsize<U>,U:Sync+Send+?Sized {}
+#[cfg(feature="coerce_unsized")]
+impl<T,U> std::ops::CoerceUnsized<WeakCura<U>> for WeakCura<T>
+    where T:Sync+Send+?Sized+std::marker::Unsize<U>,U:Sync+Send+?Sized {}
+
+/**
+ *  implement send and sync since thats all we want
+ */
+unsafe impl<T:  Send + Sync + ?Sized> Send for Cura<T> {}
+unsafe impl<T:  Send + Sync + ?Sized> Sync for Cura<T> {}
+
+/**
+ *  deref to make use simpler, this should also transparently
+ *  read-lock
+ */
+//TBD  feed this to chatgpt
+/*
+impl<T:Sync+Send> Deref for Cura<T>
+{
+    type Target = ReadGuard<T>;
+    //TBD this should probably return a reference to readguard?
+    fn deref(&self) -> &Self::Target {
+        todo!("this deref should actually do a 'read()' ");
+        //&self.data().data
+        &self.read()
+    }
+}*/
+/**
+ *  clone to make new references of the object
+ */
+impl<T:  Sync + Send +?Sized> Clone for Cura<T> {
+    fn clone(&self) -> Self {
+        self.data().head.count.fetch_add(1, Relaxed);
+        Cura {
+            ptr: self.ptr,
+            phantom:PhantomData,
+            //dummy:0,
+            }
+    }
+}
+/**
+ *  drop to clean up references
+ */
+impl<T:  Sync + Send + ?Sized> Drop for Cura<T> {
+    fn drop(&mut self) {
+        if self.data().head.count.fetch_sub(1, Release) == 1 {
+            fence(Acquire);
+            if self.state().load(Relaxed)&(WRITER|READERS)!=0
+            {
+                self.leaked();
+            }
+            unsafe {
+                std::ptr::drop_in_place(self.data().data.get());
+            }
+            //  release the weak reference held by all the strong ones
+            drop(WeakCura{
+                ptr:self.ptr,
+                phantom:PhantomData,
+            });
+        }
+    }
+}
+/**
+ *  WeakCura public interface
+ */
+impl<T:  Sync + Send + ?Sized> WeakCura<T> {
+    ///
+    /// attempt to get a Cura back from a weak reference,returns
+    /// None if the value has already been dropped
+    ///
+    pub fn upgrade(&self)->Option<Cura<T>>
+    {
+        let count=self.data().head.count.fetch_update(
+                                    Acquire,
+                                    Relaxed,
+                                    |x|{
+                                        if x==0{
+                                            None
+                                        }else{
+                                            Some(x+1)
+                                        }
+                                    });
+        match count {
+            Err(_)=>None,
+            Ok(_)=>Some(Cura{
+                ptr:self.ptr,
+                phantom:PhantomData,
+            }),
+        }
+    }
+    ///
+    /// number of Curae pointing to this value
+    ///
+    pub fn strong_count(&self)->usize
+    {
+        self.data().head.count.load(Acquire)
+    }
+    ///
+    /// number of WeakCurae pointing to this value
+    ///
+    pub fn weak_count(&self)->usize
+    {
+        let weak=self.data().head.weakcount.load(Acquire) as usize;
+        if self.strong_count()>0
+        {
+            weak-1
+        }else{
+            weak
+        }
+    }
+    ///
+    /// util to get accesss to curadata
+    ///
+    fn data(&self) -> &CuraData<T> {
+        unsafe { self.ptr.as_ref() }
+    }
+}
+impl<T:  Sync + Send> WeakCura<T> {
+    ///
+    /// turn a WeakCura<T> into a WeakCura<U> for the unsize! macro,
+    /// like Cura::__unsize() but also for values already dropped
+    /// # Safety
+    /// same as for Cura::__unsize(), f must only coerce the pointer
+    #[doc(hidden)]
+    pub unsafe fn __unsize<U:Sync+Send+?Sized>(self,f:impl FnOnce(*mut T)->*mut U)->WeakCura<U>
+    {
+        let ptr=self.ptr.as_ptr();
+        let value=std::ptr::addr_of_mut!((*ptr).data) as *mut T;
+        let unsized_value=f(value);
+        assert_eq!(unsized_value as *mut u8,value as *mut u8,"unsize moved the pointer");
+        std::mem::forget(self);
+        WeakCura{
+            ptr:NonNull::new_unchecked(with_addr(unsized_value as *mut CuraData<U>,ptr as *mut u8)),
+            phantom:PhantomData,
+        }
+    }
+}
+impl<T:  Sync + Send +?Sized> Clone for WeakCura<T> {
+    fn clone(&self) -> Self {
+        self.data().head.add_weak();
+        WeakCura {
+            ptr: self.ptr,
+            phantom:PhantomData,
+        }
+    }
+}
+/**
+ *  drop to free the allocation once the last weak reference is gone
+ */
+impl<T:  Sync + Send + ?Sized> Drop for WeakCura<T> {
+    fn drop(&mut self) {
+        if self.data().head.weakcount.fetch_sub(1, Release) == 1 {
+            fence(Acquire);
+            unsafe {
+                //  the value is already dropped, only the memory is left
+                let layout=Layout::for_value(self.ptr.as_ref());
+                std::alloc::dealloc(self.ptr.as_ptr() as *mut u8,layout);
+            }
+        }
+    }
+}
+impl<T: Sync + Send + ?Sized> std::fmt::Debug for WeakCura<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "(WeakCura)")
+    }
+}
+unsafe impl<T:  Send + Sync + ?Sized> Send for WeakCura<T> {}
+unsafe impl<T:  Send + Sync + ?Sized> Sync for WeakCura<T> {}
+/**********************************************************
+ *  deadlock detection, keeps a wait-for graph of which thread
+ *  holds which Cura and which one it is waiting for, and panics
+ *  instead of parking if that would close a cycle
+ */
+#[cfg(feature="deadlock_detection")]
+mod deadlock
+{
+    use std::sync::{Mutex,MutexGuard};
+    use std::thread::ThreadId;
+    struct Holder
+    {
+        addr:usize,
+        thread:ThreadId,
+        name:String,
+        count:usize,
+    }
+    struct Graph
+    {
+        holders:Vec<Holder>,
+        waiting:Vec<(ThreadId,usize)>,
+    }
+    static GRAPH:Mutex<Graph>=Mutex::new(Graph{
+        holders:Vec::new(),
+        waiting:Vec::new(),
+    });
+    fn graph()->MutexGuard<'static,Graph>
+    {
+        GRAPH.lock().unwrap_or_else(|e|e.into_inner())
+    }
+    fn name()->String
+    {
+        let me=std::thread::current();
+        match me.name() {
+            Some(name)=>name.to_string(),
+            None=>format!("{:?}",me.id()),
+        }
+    }
+    ///
+    /// current thread got a lock
+    ///
+    pub fn acquired(addr:usize)
+    {
+        let me=std::thread::current().id();
+        let mut g=graph();
+        match g.holders.iter_mut().find(|h|h.addr==addr && h.thread==me) {
+            Some(h)=>{h.count+=1;},
+            None=>{
+                g.holders.push(Holder{addr,thread:me,name:name(),count:1});
+            },
+        }
+    }
+    ///
+    /// a lock registered to owner is being released, possibly
+    /// by another thread. a lock nobody registered, like one
+    /// taken by an async task, is left alone
+    ///
+    pub fn released(addr:usize,owner:Option<ThreadId>)
+    {
+        let owner=match owner {
+            Some(owner)=>owner,
+            None=>return,
+        };
+        let mut g=graph();
+        let pos=g.holders.iter().position(|h|h.addr==addr && h.thread==owner);
+        if let Some(pos)=pos
+        {
+            g.holders[pos].count-=1;
+            if g.holders[pos].count==0
+            {
+                g.holders.swap_remove(pos);
+            }
+        }
+    }
+    ///
+    /// a Cura still held by a forgotten guard is going away
+    ///
+    pub fn freed(addr:usize)
+    {
+        graph().holders.retain(|h|h.addr!=addr);
+    }
+    ///
+    /// current thread is about to park waiting for a lock, panic
+    /// if whoever holds it is (indirectly) waiting for us. holding
+    /// tells that we hold this very lock and wait for others to
+    /// leave it, which is fine as far as we are concerned
+    ///
+    pub fn waiting(addr:usize,holding:bool)
+    {
+        let me=std::thread::current().id();
+        let mut g=graph();
+        let mut path=Vec::new();
+        if find_cycle(&g,me,addr,holding,&mut path)
+        {
+            drop(g);
+            let mut msg=String::from("deadlock detected:");
+            for (waiter,addr,holder,_) in path {
+                msg.push_str(&format!(" thread '{}' waits for Cura {:#x} held by thread '{}',",
+                                      waiter,addr,holder));
+            }
+            msg.pop();
+            panic!("{}",msg);
+        }
+        g.waiting.push((me,addr));
+    }
+    ///
+    /// current thread is no longer waiting
+    ///
+    pub fn done_waiting()
+    {
+        let me=std::thread::current().id();
+        let mut g=graph();
+        if let Some(pos)=g.waiting.iter().position(|w|w.0==me)
+        {
+            g.waiting.swap_remove(pos);
+        }
+    }
+    ///
+    /// depth first search from the lock we are about to wait for
+    /// through its holders and the locks they wait for, looking
+    /// for our own thread. path collects the cycle for reporting
+    ///
+    fn find_cycle(g:&Graph,me:ThreadId,addr:usize,holding:bool,
+                  path:&mut Vec<(String,usize,String,ThreadId)>)->bool
+    {
+        let waiter=match path.last() {
+            None=>name(),
+            Some(last)=>last.2.clone(),
+        };
+        for h in g.holders.iter().filter(|h|h.addr==addr) {
+            if h.thread==me
+            {
+                if path.is_empty() && holding
+                {
+                    continue;
+                }
+                path.push((waiter,addr,h.name.clone(),h.thread));
+                return true;
+            }
+            //  already been through this one
+            if path.iter().any(|p|p.3==h.thread)
+            {
+                continue;
+            }
+            if let Some(&(_,next))=g.waiting.iter().find(|w|w.0==h.thread)
+            {
+                path.push((waiter.clone(),addr,h.name.clone(),h.thread));
+                if find_cycle(g,me,next,false,path)
+                {
+                    return true;
+                }
+                path.pop();
+            }
+        }
+        false
+    }
+}
+#[cfg(not(feature="deadlock_detection"))]
+mod deadlock
+{
+    #[inline(always)]
+    pub fn acquired(_addr:usize){}
+    #[inline(always)]
+    pub fn released(_addr:usize,_owner:Option<std::thread::ThreadId>){}
+    #[inline(always)]
+    pub fn freed(_addr:usize){}
+    #[inline(always)]
+    pub fn waiting(_addr:usize,_holding:bool){}
+    #[inline(always)]
+    pub fn done_waiting(){}
+}
+/**********************************************************
+ *  per-thread registry of the locks each thread holds, used to
+ *  tell apart a nested read that would wait on itself forever.
+ *  a guard sent to another thread still counts as held by the
+ *  thread that took it until it is dropped, which then leaves a
+ *  note in that thread's mailbox for it to pick up the next time
+ *  it looks. as that is no proof of a deadlock, only nested reads
+ *  that were asked for are checked for in every build, the rest
+ *  only with the deadlock_detection feature
+ */
+mod held
+{
+    use std::cell::RefCell;
+    use std::sync::{Arc,Mutex,MutexGuard};
+    use std::sync::atomic::{AtomicBool,Ordering::*};
+    use std::thread::ThreadId;
+    use super::LockType;
+    #[derive(Clone,Copy,Default)]
+    pub struct Held
+    {
+        pub addr:usize,
+        pub reads:usize,
+        pub writes:usize,
+        pub upgradable:usize,
+    }
+    impl Held
+    {
+        fn count(&mut self,t:LockType)->&mut usize
+        {
+            match t {
+                LockType::Read=>&mut self.reads,
+                LockType::Write=>&mut self.writes,
+                LockType::Upgradable|LockType::Upgrade=>&mut self.upgradable,
+            }
+        }
+    }
+    ///
+    /// releases other threads did on a thread's behalf
+    ///
+    #[derive(Default)]
+    struct Mailbox
+    {
+        full:AtomicBool,
+        notes:Mutex<Vec<(usize,Option<LockType>)>>, //None for a freed Cura
+    }
+    impl Mailbox
+    {
+        fn post(&self,addr:usize,t:Option<LockType>)
+        {
+            let mut notes=self.notes.lock().unwrap_or_else(|e|e.into_inner());
+            notes.push((addr,t));
+            self.full.store(true,Release);
+        }
+    }
+    ///
+    /// mailboxes of the threads still around to read them
+    ///
+    static MAILBOXES:Mutex<Vec<(ThreadId,Arc<Mailbox>)>>=Mutex::new(Vec::new());
+    fn mailboxes()->MutexGuard<'static,Vec<(ThreadId,Arc<Mailbox>)>>
+    {
+        MAILBOXES.lock().unwrap_or_else(|e|e.into_inner())
+    }
+    struct Registry
+    {
+        me:ThreadId,
+        held:RefCell<Vec<Held>>,
+        mailbox:Arc<Mailbox>,
+    }
+    impl Registry
+    {
+        fn new()->Registry
+        {
+            let me=std::thread::current().id();
+            let mailbox=Arc::new(Mailbox::default());
+            mailboxes().push((me,mailbox.clone()));
+            Registry{me,held:RefCell::new(Vec::new()),mailbox}
+        }
+        ///
+        /// apply the notes left for us
+        ///
+        fn drain(&self)
+        {
+            if !self.mailbox.full.load(Acquire)
+            {
+                return;
+            }
+            let notes={
+                let mut notes=self.mailbox.notes.lock().unwrap_or_else(|e|e.into_inner());
+                self.mailbox.full.store(false,Relaxed);
+                std::mem::take(&mut *notes)
+            };
+            let mut held=self.held.borrow_mut();
+            for (addr,t) in notes {
+                match t {
+                    Some(t)=>release(&mut held,addr,t),
+                    None=>held.retain(|e|e.addr!=addr),
+                }
+            }
+        }
+    }
+    impl Drop for Registry
+    {
+        fn drop(&mut self)
+        {
+            mailboxes().retain(|m|m.0!=self.me);
+        }
+    }
+    thread_local!{
+        static REGISTRY:Registry=Registry::new();
+    }
+    fn release(held:&mut Vec<Held>,addr:usize,t:LockType)
+    {
+        if let Some(pos)=held.iter().position(|e|e.addr==addr)
+        {
+            let c=held[pos].count(t);
+            *c=c.saturating_sub(1);
+            let e=held[pos];
+            if e.reads==0 && e.writes==0 && e.upgradable==0
+            {
+                held.swap_remove(pos);
+            }
+        }
+    }
+    ///
+    /// the current thread, if it has a registry
+    ///
+    pub fn me()->Option<ThreadId>
+    {
+        REGISTRY.try_with(|r|r.me).ok()
+    }
+    ///
+    /// current thread got a lock
+    ///
+    pub fn acquired(addr:usize,t:LockType)
+    {
+        let _=REGISTRY.try_with(|r|{
+            r.drain();
+            let mut h=r.held.borrow_mut();
+            match h.iter_mut().find(|e|e.addr==addr) {
+                Some(e)=>{*e.count(t)+=1;},
+                None=>{
+                    let mut e=Held{addr,..Default::default()};
+                    *e.count(t)=1;
+                    h.push(e);
+                },
+            }
+        });
+    }
+    ///
+    /// a lock registered to owner, if any, is being released
+    /// by the current thread
+    ///
+    pub fn released(addr:usize,t:LockType,owner:Option<ThreadId>)
+    {
+        let owner=match owner {
+            Some(owner)=>owner,
+            None=>return,
+        };
+        if REGISTRY.try_with(|r|{
+            if r.me==owner
+            {
+                release(&mut r.held.borrow_mut(),addr,t);
+            }
+            r.me==owner
+        }).unwrap_or(false)
+        {
+            return;
+        }
+        let mailbox=mailboxes().iter().find(|m|m.0==owner).map(|m|m.1.clone());
+        if let Some(mailbox)=mailbox
+        {
+            mailbox.post(addr,Some(t));
+        }
+    }
+    ///
+    /// a Cura left locked by a forgotten guard is going away, so
+    /// its address is free for another one nobody holds yet
+    ///
+    pub fn freed(addr:usize)
+    {
+        let me=me();
+        let _=REGISTRY.try_with(|r|r.held.borrow_mut().retain(|e|e.addr!=addr));
+        for m in mailboxes().iter().filter(|m|Some(m.0)!=me) {
+            m.1.post(addr,None);
+        }
+    }
+    ///
+    /// what the current thread holds of a lock
+    ///
+    pub fn get(addr:usize)->Held
+    {
+        REGISTRY.try_with(|r|{
+            r.drain();
+            r.held.borrow().iter().find(|e|e.addr==addr).copied()
+        }).ok().flatten().unwrap_or_default()
+    }
+    ///
+    /// panic if the current thread is about to wait for a lock
+    /// that only it can release
+    ///
+    pub fn check(addr:usize,t:LockType)
+    {
+        let held=get(addr);
+        let me=std::thread::current();
+        let name=me.name().unwrap_or("<unnamed>");
+        match t {
+            LockType::Read if held.writes>0 && cfg!(feature="deadlock_detection")=>{
+                panic!("deadlock detected: thread '{}' tried to readlock a Cura it is writing",name);
+            },
+            LockType::Read if held.reads>0 || held.upgradable>0=>{
+                panic!("deadlock detected: thread '{}' tried to readlock a Cura it is already \
+                        reading while a writer waits, use set_reentrant(true) to allow this",name);
+            },
+            LockType::Write|LockType::Upgradable if (held.reads>0 || held.writes>0 || held.upgradable>0)
+                && cfg!(feature="deadlock_detection")=>{
+                panic!("deadlock detected: thread '{}' tried to lock a Cura it already holds",name);
+            },
+            LockType::Upgrade if held.reads>0 && cfg!(feature="deadlock_detection")=>{
+                panic!("deadlock detected: thread '{}' tried to upgrade while also reading \
+                        the same Cura",name);
+            },
+            _=>{},
+        }
+    }
+}
+/**********************************************************
+ *  locking several Curae at once
+ */
+///
+/// ask lock_all() for a readlock on a Cura
+///
+pub struct ReadLock<'a,T:Sync+Send+?Sized>(pub &'a Cura<T>);
+///
+/// ask lock_all() for a writelock on a Cura
+///
+pub struct WriteLock<'a,T:Sync+Send+?Sized>(pub &'a Cura<T>);
+impl<T:Sync+Send+?Sized> Clone for ReadLock<'_,T> {
+    fn clone(&self)->Self{*self}
+}
+impl<T:Sync+Send+?Sized> Copy for ReadLock<'_,T> {}
+impl<T:Sync+Send+?Sized> Clone for WriteLock<'_,T> {
+    fn clone(&self)->Self{*self}
+}
+impl<T:Sync+Send+?Sized> Copy for WriteLock<'_,T> {}
+///
+/// a single lock request that lock_all() knows how to take
+///
+pub trait LockRequest
+{
+    /// the guard we get when the lock is taken
+    type Guard;
+    /// address used to decide the locking order
+    fn address(&self)->usize;
+    /// block until the lock is taken
+    fn acquire(self)->Self::Guard;
+}
+impl<'a,T:Sync+Send+?Sized> LockRequest for ReadLock<'a,T>
+{
+    type Guard=ReadGuard<'a,T>;
+    fn address(&self)->usize{
+        self.0.addr()
+    }
+    fn acquire(self)->ReadGuard<'a,T>{
+        self.0.read()
+    }
+}
+impl<'a,T:Sync+Send+?Sized> LockRequest for WriteLock<'a,T>
+{
+    type Guard=Guard<'a,T>;
+    fn address(&self)->usize{
+        self.0.addr()
+    }
+    fn acquire(self)->Guard<'a,T>{
+        self.0.write()
+    }
+}
+///
+/// a set of lock requests that lock_all() takes in one go,
+/// implemented for tuples of requests and slices/vecs of them.
+/// the requests in a slice or vec are all of the same type, so
+/// they are either all reads or all writes. mixing the two takes
+/// a tuple
+///
+pub trait LockSet
+{
+    /// the guards we get back, in the same shape as the requests
+    type Guards;
+    /// take all the locks in address order
+    fn lock(self)->Self::Guards;
+}
+///
+/// lock several Curae at once and call a lambda with the guards.
+/// the locks are always taken in the order of their addresses
+/// so two threads locking the same Curae can never deadlock on
+/// each other, no matter what order they list them in.
+/// passing the same Cura twice panics
+/// ```
+/// use cura::{Cura,ReadLock,WriteLock,lock_all};
+/// let a=Cura::new(1);
+/// let b=Cura::new(2);
+/// let c=Cura::new(0);
+/// let sum=lock_all((ReadLock(&a),ReadLock(&b),WriteLock(&c)),|(a,b,mut c)|{
+///     *c=*a+*b;
+///     *c
+/// });
+/// assert_eq!(sum,3);
+/// let v=vec![WriteLock(&a),WriteLock(&b)];
+/// lock_all(v,|mut guards|{
+///     for g in guards.iter_mut(){
+///         **g+=1;
+///     }
+/// });
+/// assert_eq!(*a.read(),2);
+/// ```
+pub fn lock_all<L:LockSet,R>(locks:L,f:impl FnOnce(L::Guards)->R)->R
+{
+    f(locks.lock())
+}
+///
+/// figure out in which order to take locks at the given addresses
+///
+fn lock_order(addresses:&[usize])->Vec<usize>
+{
+    let mut order:Vec<usize>=(0..addresses.len()).collect();
+    order.sort_by_key(|&i|addresses[i]);
+    if order.windows(2).any(|w|addresses[w[0]]==addresses[w[1]])
+    {
+        panic!("the same Cura was given to lock_all more than once");
+    }
+    order
+}
+macro_rules! lockset_tuple {
+    ($($name:ident $idx:tt),+) => {
+        impl<$($name:LockRequest),+> LockSet for ($($name,)+)
+        {
+            type Guards=($($name::Guard,)+);
+            fn lock(self)->Self::Guards
+            {
+                let order=lock_order(&[$(self.$idx.address()),+]);
+                let mut requests=($(Some(self.$idx),)+);
+                let mut guards=($(None::<$name::Guard>,)+);
+                for i in order {
+                    match i {
+                        $($idx=>{
+                            guards.$idx=requests.$idx.take().map(|r|r.acquire());
+                        },)+
+                        _=>unreachable!(),
+                    }
+                }
+                ($(guards.$idx.unwrap(),)+)
+            }
+        }
+    };
+}
+lockset_tuple!(A 0);
+lockset_tuple!(A 0,B 1);
+lockset_tuple!(A 0,B 1,C 2);
+lockset_tuple!(A 0,B 1,C 2,D 3);
+lockset_tuple!(A 0,B 1,C 2,D 3,E 4);
+lockset_tuple!(A 0,B 1,C 2,D 3,E 4,F 5);
+lockset_tuple!(A 0,B 1,C 2,D 3,E 4,F 5,G 6);
+lockset_tuple!(A 0,B 1,C 2,D 3,E 4,F 5,G 6,H 7);
+impl<L:LockRequest> LockSet for Vec<L>
+{
+    type Guards=Vec<L::Guard>;
+    fn lock(self)->Vec<L::Guard>
+    {
+        let order=lock_order(&self.iter().map(|r|r.address()).collect::<Vec<_>>());
+        let mut requests:Vec<Option<L>>=self.into_iter().map(Some).collect();
+        let mut guards:Vec<Option<L::Guard>>=requests.iter().map(|_|None).collect();
+        for i in order {
+            guards[i]=requests[i].take().map(|r|r.acquire());
+        }
+        guards.into_iter().map(|g|g.unwrap()).collect()
+    }
+}
+impl<L:LockRequest+Copy> LockSet for &[L]
+{
+    type Guards=Vec<L::Guard>;
+    fn lock(self)->Vec<L::Guard>
+    {
+        self.to_vec().lock()
+    }
+}
+/**********************************************************
+ *  async locking
+ */
+///
+/// future returned by read_async()
+///
+#[must_use = "futures do nothing unless polled"]
+pub struct ReadFuture<'a,T:Send+Sync+?Sized>
+{
+    cura:&'a Cura<T>,
+    link:Option<QueueLink>, //our place in the queue, if we are in it
+}
+impl<'a,T:Send+Sync+?Sized> Future for ReadFuture<'a,T>
+{
+    type Output=ReadGuard<'a,T>;
+    fn poll(self:Pin<&mut Self>,cx:&mut Context<'_>)->Poll<ReadGuard<'a,T>>
+    {
+        //  the link is never moved out while pinned
+        let this=unsafe{self.get_unchecked_mut()};
+        match this.cura.poll_lock(&mut this.link,LockType::Read,cx) {
+            Poll::Pending=>Poll::Pending,
+            Poll::Ready(())=>Poll::Ready(ReadGuard{
+                cura:this.cura,
+                taken:Taken::unowned(),
+            }),
+        }
+    }
+}
+impl<T:Send+Sync+?Sized> Drop for ReadFuture<'_,T>
+{
+    fn drop(&mut self) {
+        if let Some(link)=&mut self.link
+        {
+            //  the lock came to us just now, pass it on
+            if self.cura.leave_queue(link)==Wakeup::Handed
+            {
+                self.cura.unlock_fair(LockType::Read,Taken::unowned());
+            }
+        }
+    }
+}
+///
+/// future returned by write_async()
+///
+#[must_use = "futures do nothing unless polled"]
+pub struct WriteFuture<'a,T:Send+Sync+?Sized>
+{
+    cura:&'a Cura<T>,
+    link:Option<QueueLink>, //our place in the queue, if we are in it
+}
+impl<'a,T:Send+Sync+?Sized> Future for WriteFuture<'a,T>
+{
+    type Output=Guard<'a,T>;
+    fn poll(self:Pin<&mut Self>,cx:&mut Context<'_>)->Poll<Guard<'a,T>>
+    {
+        //  the link is never moved out while pinned
+        let this=unsafe{self.get_unchecked_mut()};
+        match this.cura.poll_lock(&mut this.link,LockType::Write,cx) {
+            Poll::Pending=>Poll::Pending,
+            Poll::Ready(())=>Poll::Ready(Guard{
+                cura:this.cura,
+                taken:Taken::unowned(),
+            }),
+        }
+    }
+}
+impl<T:Send+Sync+?Sized> Drop for WriteFuture<'_,T>
+{
+    fn drop(&mut self) {
+        if let Some(link)=&mut self.link
+        {
+            if self.cura.leave_queue(link)==Wakeup::Handed
+            {
+                self.cura.unlock_fair(LockType::Write,Taken::unowned());
+            }
+        }
+    }
+}
+/**
+ *  the link is only ever touched with the queue locked, or by
+ *  whoever took it out of the queue until it is settled
+ */
+unsafe impl<T:Send+Sync+?Sized> Send for ReadFuture<'_,T> {}
+unsafe impl<T:Send+Sync+?Sized> Sync for ReadFuture<'_,T> {}
+unsafe impl<T:Send+Sync+?Sized> Send for WriteFuture<'_,T> {}
+unsafe impl<T:Send+Sync+?Sized> Sync for WriteFuture<'_,T> {}
+/**********************************************************
+ *  guards
+ */
+///
+/// how a guard came by its lock
+///
+#[derive(Clone,Copy)]
+struct Taken
+{
+    owner:Option<ThreadId>, //registered as holding it, see mod held
+    panicking:bool, //already panicking then, see Cura::poison()
+}
+impl Taken
+{
+    ///
+    /// taken just now by this thread
+    ///
+    fn now()->Taken
+    {
+        Taken{
+            owner:held::me(),
+            panicking:std::thread::panicking(),
+        }
+    }
+    ///
+    /// taken just now but not registered to any thread, like
+    /// the locks of async tasks and owned guards
+    ///
+    fn unowned()->Taken
+    {
+        Taken{
+            owner:None,
+            panicking:std::thread::panicking(),
+        }
+    }
+}
+///
+/// writeguard for Cura
+///
+#[must_use = "if unused the Lock will immediately unlock"]
+#[clippy::has_significant_drop]
+pub struct Guard<'a,T: Send+Sync+?Sized>
+{
+    cura:&'a Cura<T>,
+    taken:Taken,
+}
+impl<'a,T:Send+Sync+?Sized> Guard<'a,T>
+{
+    ///
+    /// turn this writeguard into a readguard without letting
+    /// any other writer in between, queued readers get to join
+    /// ```
+    /// use cura::Cura;
+    /// let s=Cura::new(1);
+    /// let mut w=s.write();
+    /// *w=2;
+    /// let r=w.downgrade();
+    /// assert_eq!(*r,2);
+    /// assert!(s.try_read().is_some());
+    /// assert!(s.try_write().is_none());
+    /// ```
+    pub fn downgrade(self)->ReadGuard<'a,T>
+    {
+        let cura=self.cura;
+        let taken=self.taken;
+        std::mem::forget(self);
+        cura.downgradelock(taken);
+        ReadGuard{
+            cura,
+            taken:Taken::now(),
+        }
+    }
     ///
-    fn data(&self) -> &CuraData<T> {
-        unsafe { self.ptr.as_ref() }
+    /// make a guard that only gives access to a part of the
+    /// locked value, keeping the whole thing locked
+    /// ```
+    /// use cura::Cura;
+    /// struct Foo{a:i32,b:Vec<i32>}
+    /// let s=Cura::new(Foo{a:1,b:vec![1,2,3]});
+    /// {
+    ///     let mut b=s.write().map(|f|&mut f.b[1..]);
+    ///     b[0]=5;
+    ///     assert!(s.try_read().is_none());
+    /// }
+    /// assert_eq!(s.read().b,vec![1,5,3]);
+    /// ```
+    pub fn map<U:?Sized+'a>(self,f:impl FnOnce(&mut T)->&mut U)->MappedGuard<'a,T,U>
+    {
+        let mut s=self;
+        let data:*mut U=f(&mut *s);
+        let cura=s.cura;
+        let taken=s.taken;
+        std::mem::forget(s);
+        MappedGuard{
+            cura,
+            data,
+            taken,
+        }
     }
     ///
-    /// util to get access to the internal queuedata
+    /// like map() but the projection can fail, in which case the
+    /// original guard is given back along with the error
     ///
-    fn get_queuedata(&self) -> *mut QueueData
+    pub fn try_map<U:?Sized+'a,E>(self,f:impl FnOnce(&mut T)->Result<&mut U,E>)
+        ->Result<MappedGuard<'a,T,U>,(Self,E)>
     {
-        self.data().queuedata.get()
+        let mut s=self;
+        let data=match f(&mut *s) {
+            Ok(data)=>data as *mut U,
+            Err(e)=>{return Err((s,e));},
+        };
+        let cura=s.cura;
+        let taken=s.taken;
+        std::mem::forget(s);
+        Ok(MappedGuard{
+            cura,
+            data,
+            taken,
+        })
     }
-    /*
-    ///
-    /// compare queue count to LOCḰQUEUE to see if it is already
-    /// locked
     ///
-    fn queue_locked(&self)->bool{
-        self.data().queuecount.load(Acquire)>=LOCKQUEUE
-    }*/
+    /// like map() but the projection may come up empty, in which
+    /// case the original guard is given back
     ///
-    /// spin until we can acquire a lock on queue by incrementing
-    /// it with LOCKQUEUE
+    pub fn filter_map<U:?Sized+'a>(self,f:impl FnOnce(&mut T)->Option<&mut U>)
+        ->Result<MappedGuard<'a,T,U>,Self>
+    {
+        self.try_map(|t|f(t).ok_or(())).map_err(|(s,_)|s)
+    }
     ///
-    fn lock_queue(&self)
+    /// let go of the lock for the duration of f, taking it back
+    /// afterwards even if f panics
+    /// ```
+    /// use cura::Cura;
+    /// let s=Cura::new(1);
+    /// let mut w=s.write();
+    /// *w=2;
+    /// w.unlocked(||{
+    ///     assert_eq!(*s.read(),2);
+    /// });
+    /// *w+=1;
+    /// ```
+    pub fn unlocked<R>(&mut self,f:impl FnOnce()->R)->R
     {
-        loop{
-            let lock=self.data().queuecount.fetch_update(
-                                        SeqCst,
-                                        SeqCst,
-                                        |x|{
-                                            if x<LOCKQUEUE{
-                                                Some(x+LOCKQUEUE)
-                                            }else{
-                                                None
-                                            }
-                                        });
-            match lock {
-                Err(_)=>{
-                    /*  it is already locked, so we spin*/
-                    std::hint::spin_loop();
-                },
-                Ok(_x)=>{
-                    /*  locked successfully*/
-                    break;
-                },
-            }
-
-        }
+        self.cura.unwritelock(self.taken);
+        let _relock=Relock(self.cura,LockType::Write,&mut self.taken);
+        f()
     }
     ///
-    /// check that queue is locked and unlock it by decrementing
-    /// by LOCKQUEUE
+    /// release the lock straight to whoever is first in the
+    /// queue instead of letting anyone grab it on the way
     ///
-    fn unlock_queue(&self)
+    pub fn unlock_fair(self)
     {
-        let _lock=self.data().queuecount.fetch_update(
-                                    SeqCst,
-                                    SeqCst,
-                                    |x|{
-                                        if x<LOCKQUEUE {
-                                            panic!("trying to unlock nonlocked queue");
-                                        }else{
-                                            Some(x-LOCKQUEUE)
-                                        }
-                                    });
+        let cura=self.cura;
+        let taken=self.taken;
+        std::mem::forget(self);
+        cura.unlock_fair(LockType::Write,taken);
     }
     ///
-    /// lock queue and insert ourselves to it and park
-    /// waiting for the time in the future when we are
-    /// unparked as the first in the queue
+    /// let whoever is first in the queue have the lock and get in
+    /// line for it again, does nothing if nobody is waiting
     ///
-    fn enqueue(&self,t:LockType){
+    pub fn bump(&mut self)
+    {
+        self.taken=self.cura.bump(LockType::Write,self.taken);
+    }
+}
+impl<T:Send+Sync+?Sized> Drop for Guard<'_,T>
+{
+    fn drop(&mut self) {
+        self.cura.unwritelock(self.taken); //TBD no need to do anything else?
+    }
+}
+impl<T: Sync + Send+?Sized> Deref for Guard<'_,T> {
+    type Target = T;
+    fn deref(&self) -> &T { //TBD reference lifetime?
+        unsafe{
+            &*self.cura.data().data.get()
+        }
+    }
+}
+impl<T: Sync + Send + ?Sized> DerefMut for Guard<'_,T> {
+    fn deref_mut(&mut self) -> &mut T { //TBD reference lifetime?
+        unsafe {
+            &mut *self.cura.data().data.get()
+        }
+    }
+}
 
-        //  lock and increment queue size
-        self.lock_queue();
-        self.inc_queue();
 
-        //  insert ourselves into queue
-        unsafe{
-            (*self.get_queuedata()).enqueue(t);
+///
+/// takes a lock let go by unlocked() back when dropped, which
+/// also happens when unwinding. the lock is taken anew by this
+/// thread, so the guard's Taken is too
+///
+struct Relock<'a,T:Send+Sync+?Sized>(&'a Cura<T>,LockType,&'a mut Taken);
+impl<T:Send+Sync+?Sized> Drop for Relock<'_,T>
+{
+    fn drop(&mut self) {
+        match self.1 {
+            LockType::Read=>{self.0.readlock(None);},
+            LockType::Write=>{self.0.writelock(None);},
+            _=>unreachable!("only reads and writes are relocked"),
         }
-        //  unlock queue for others to modify and see
-        self.unlock_queue();
+        *self.2=Taken::now();
+    }
+}
 
-        //  and park, ready to spin on return
-        loop{
-            std::thread::park();
-            self.lock_queue();
-            let amfirst=unsafe{
-                    (*(*self.get_queuedata()).queue).thread.id()==std::thread::current().id()
-                };
-            if amfirst
-            {
-                unsafe{
-                    (*self.get_queuedata()).dequeue();
-                }
-                self.dec_queue();
-                self.unlock_queue();
-                break;
-            }else{
-                self.wakenext();
-                self.unlock_queue();
-            }
+/**
+ *  readguard for Cura
+ */
+#[must_use = "if unused the Lock will immediately unlock"]
+#[clippy::has_significant_drop]
+pub struct ReadGuard<'a,T:Send+Sync+?Sized>
+{
+    cura:&'a Cura<T>,
+    taken:Taken,
+}
+impl<'a,T:Send+Sync+?Sized> ReadGuard<'a,T>
+{
+    ///
+    /// make a guard that only gives access to a part of the
+    /// locked value, keeping the whole thing readlocked
+    /// ```
+    /// use cura::Cura;
+    /// struct Foo{a:i32,b:String}
+    /// let s=Cura::new(Foo{a:1,b:"foo".to_string()});
+    /// let b=s.read().map(|f|f.b.as_str());
+    /// assert_eq!(&*b,"foo");
+    /// ```
+    pub fn map<U:?Sized+'a>(self,f:impl FnOnce(&T)->&U)->MappedReadGuard<'a,T,U>
+    {
+        let data=NonNull::from(f(&*self));
+        let cura=self.cura;
+        let taken=self.taken;
+        std::mem::forget(self);
+        MappedReadGuard{
+            cura,
+            data,
+            taken,
         }
     }
     ///
-    /// increment number of threads blocked in queue
+    /// like map() but the projection can fail, in which case the
+    /// original guard is given back along with the error
     ///
-    fn inc_queue(&self)
+    pub fn try_map<U:?Sized+'a,E>(self,f:impl FnOnce(&T)->Result<&U,E>)
+        ->Result<MappedReadGuard<'a,T,U>,(Self,E)>
     {
-        self.data().queuecount.fetch_add(
-                                    1,
-                                    SeqCst);
+        let data=match f(&*self) {
+            Ok(data)=>NonNull::from(data),
+            Err(e)=>{return Err((self,e));},
+        };
+        let cura=self.cura;
+        let taken=self.taken;
+        std::mem::forget(self);
+        Ok(MappedReadGuard{
+            cura,
+            data,
+            taken,
+        })
     }
     ///
-    /// decrement number of threads blocked in queue
+    /// like map() but the projection may come up empty, in which
+    /// case the original guard is given back
     ///
-    fn dec_queue(&self)
+    pub fn filter_map<U:?Sized+'a>(self,f:impl FnOnce(&T)->Option<&U>)
+        ->Result<MappedReadGuard<'a,T,U>,Self>
     {
-        self.data().queuecount.fetch_sub(1,SeqCst);
+        self.try_map(|t|f(t).ok_or(())).map_err(|(s,_)|s)
     }
     ///
-    /// find out approximate size of queue
-    ///
-    fn queue_size(&self)->u32
+    /// let go of the readlock for the duration of f, taking it
+    /// back afterwards even if f panics
+    /// ```
+    /// use cura::Cura;
+    /// let s=Cura::new(1);
+    /// let mut r=s.read();
+    /// r.unlocked(||{
+    ///     *s.write()=2;
+    /// });
+    /// assert_eq!(*r,2);
+    /// ```
+    pub fn unlocked<R>(&mut self,f:impl FnOnce()->R)->R
     {
-        self.data().queuecount.load(Acquire)
+        self.cura.unreadlock(self.taken);
+        let _relock=Relock(self.cura,LockType::Read,&mut self.taken);
+        f()
     }
     ///
-    /// assumes queue is already locked by us
+    /// release the readlock, and if it was the last one hand the
+    /// lock straight to whoever is first in the queue
     ///
-    fn wakenext(&self)
+    pub fn unlock_fair(self)
     {
-        unsafe{
-            if !(*self.get_queuedata()).queue.is_null()
-            {
-                (*(*self.get_queuedata()).queue).thread.unpark();
-            }
-        }
+        let cura=self.cura;
+        let taken=self.taken;
+        std::mem::forget(self);
+        cura.unlock_fair(LockType::Read,taken);
     }
     ///
-    /// wake reader  in front of queue
+    /// if ours is the last readlock and someone is waiting for
+    /// the lock, let them have it and get in line again
     ///
-    fn wakereader(&self)
+    pub fn bump(&mut self)
     {
-        self.lock_queue();
+        self.taken=self.cura.bump(LockType::Read,self.taken);
+    }
+}
+impl<T:Send+Sync+?Sized> Drop for ReadGuard<'_,T>
+{
+    fn drop(&mut self) {
+        self.cura.unreadlock(self.taken); //TBD nothing else?
+    }
+}
+impl<T: Sync + Send + ?Sized> Deref for ReadGuard<'_,T> {
+    type Target = T;
+    fn deref(&self) -> &T {
         unsafe{
-            let qdata=self.get_queuedata();
-            if !(*qdata).queue.is_null()
-            {
-                if (*(*qdata).queue).lock==LockType::Read
-                {
-                    (*(*qdata).queue).thread.unpark();
-                }
-            }
+            &*self.cura.data().data.get()
         }
-        self.unlock_queue();
     }
+}
+/**
+ *  upgradable readguard for Cura
+ */
+#[must_use = "if unused the Lock will immediately unlock"]
+#[clippy::has_significant_drop]
+pub struct UpgradableReadGuard<'a,T:Send+Sync+?Sized>
+{
+    cura:&'a Cura<T>,
+    taken:Taken,
+}
+impl<'a,T:Send+Sync+?Sized> UpgradableReadGuard<'a,T>
+{
     ///
-    /// release write lock
+    /// turn this into a writeguard, waiting for the other readers
+    /// to leave. no writer can get in between
     ///
-    fn unwritelock(&self)
+    pub fn upgrade(self)->Guard<'a,T>
     {
-        self.lock_queue();
-        self.wakenext();
-        self.unlock_queue();
-        let lock=self.data().lockcount.compare_exchange(
-                                    LOCKED,FREE,SeqCst,SeqCst);
-        match lock {
-            Ok(LOCKED) =>{}, //ok
-            Ok(x)=>panic!("was supposed to be locked but was {}",x),
-            Err(x)=>panic!("was supposed to be locked but was {}",x),
+        let cura=self.cura;
+        let taken=self.taken;
+        std::mem::forget(self);
+        cura.upgradelock(taken);
+        Guard{
+            cura,
+            taken:Taken::now(),
         }
     }
     ///
-    /// decrement number of readlocks held
-    ///
-    fn unreadlock(&self)
+    /// attempt to turn this into a writeguard without waiting,
+    /// giving the guard back if there are other readers around
+    /// ```
+    /// use cura::Cura;
+    /// let s=Cura::new(1);
+    /// let u=s.upgradable_read();
+    /// let r=s.read();
+    /// let u=u.try_upgrade().unwrap_err();
+    /// drop(r);
+    /// let mut w=u.try_upgrade().ok().unwrap();
+    /// *w=2;
+    /// ```
+    pub fn try_upgrade(self)->Result<Guard<'a,T>,Self>
     {
-        let lock=self.data().lockcount.fetch_sub(1,SeqCst);
-        if lock<1
+        if self.cura.try_upgradelock(self.taken)
         {
-            panic!("was supposed to be readlocked but was {}",1);
+            let cura=self.cura;
+            std::mem::forget(self);
+            Ok(Guard{
+                cura,
+                taken:Taken::now(),
+            })
+        }else{
+            Err(self)
         }
-        self.lock_queue();
-        self.wakenext();
-        self.unlock_queue();
     }
 }
-
-impl<T: Sync + Send + ?Sized + std::fmt::Debug> std::fmt::Debug for Cura<T> {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        std::fmt::Debug::fmt(&*self.read(), f)
+impl<T:Send+Sync+?Sized> Drop for UpgradableReadGuard<'_,T>
+{
+    fn drop(&mut self) {
+        self.cura.unupgradablelock(self.taken);
     }
 }
-impl<T: Sync + Send + ?Sized + std::fmt::Display> std::fmt::Display for Cura<T> {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        std::fmt::Display::fmt(&*self.read(), f)
+impl<T: Sync + Send + ?Sized> Deref for UpgradableReadGuard<'_,T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        unsafe{
+            &*self.cura.data().data.get()
+        }
     }
 }
 /**
- * implement equality and ordering. if you mutate this value, you will get weird
- * behavior , but it is what it is.
+ *  readguard for a part of the value in a Cura
  */
-impl<T: Sync + Send + ?Sized + PartialEq> PartialEq for Cura<T> {
-    fn eq(&self, other: &Self) -> bool {
-        *self.read() == *other.read()
-    }
+#[must_use = "if unused the Lock will immediately unlock"]
+#[clippy::has_significant_drop]
+pub struct MappedReadGuard<'a,T:Send+Sync+?Sized,U:?Sized>
+{
+    cura:&'a Cura<T>,
+    data:NonNull<U>,
+    taken:Taken,
 }
-impl<T: Sync + Send + ?Sized + Eq> Eq for Cura<T> {}
-impl<T: Sync + Send + ?Sized + PartialOrd> PartialOrd for Cura<T> {
-    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
-        (*self.read()).partial_cmp(&*other.read())
+impl<'a,T:Send+Sync+?Sized,U:?Sized> MappedReadGuard<'a,T,U>
+{
+    ///
+    /// project further into the value
+    ///
+    pub fn map<V:?Sized+'a>(self,f:impl FnOnce(&U)->&V)->MappedReadGuard<'a,T,V>
+    {
+        let data=NonNull::from(f(&*self));
+        let cura=self.cura;
+        let taken=self.taken;
+        std::mem::forget(self);
+        MappedReadGuard{
+            cura,
+            data,
+            taken,
+        }
+    }
+    ///
+    /// project further into the value, giving the guard back
+    /// along with the error if that fails
+    ///
+    pub fn try_map<V:?Sized+'a,E>(self,f:impl FnOnce(&U)->Result<&V,E>)
+        ->Result<MappedReadGuard<'a,T,V>,(Self,E)>
+    {
+        let data=match f(&*self) {
+            Ok(data)=>NonNull::from(data),
+            Err(e)=>{return Err((self,e));},
+        };
+        let cura=self.cura;
+        let taken=self.taken;
+        std::mem::forget(self);
+        Ok(MappedReadGuard{
+            cura,
+            data,
+            taken,
+        })
+    }
+    ///
+    /// project further into the value, giving the guard back
+    /// if there is nothing there
+    ///
+    pub fn filter_map<V:?Sized+'a>(self,f:impl FnOnce(&U)->Option<&V>)
+        ->Result<MappedReadGuard<'a,T,V>,Self>
+    {
+        self.try_map(|u|f(u).ok_or(())).map_err(|(s,_)|s)
     }
 }
-impl<T: Sync + Send + ?Sized + Ord> Ord for Cura<T> {
-    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
-        (*self.read()).cmp(&*other.read())
+impl<T:Send+Sync+?Sized,U:?Sized> Drop for MappedReadGuard<'_,T,U>
+{
+    fn drop(&mut self) {
+        self.cura.unreadlock(self.taken);
     }
 }
-/**
- *  hash by read-locking and hashing the value, this is not super efficient but
- *  it ensures consistency while hashing
- *
- *  naturally , if you use this as a hash key and mutate the value while it is in the hash,
- *  you will get weird behavior
- */
-impl<T: Sync + Send + ?Sized + std::hash::Hash> std::hash::Hash for Cura<T> {
-    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
-        (*self.read()).hash(state);
+impl<T: Sync + Send + ?Sized,U:?Sized> Deref for MappedReadGuard<'_,T,U> {
+    type Target = U;
+    fn deref(&self) -> &U {
+        unsafe{
+            self.data.as_ref()
+        }
     }
 }
-
-/**
- *  implement send and sync since thats all we want
- */
-unsafe impl<T:  Send + Sync + ?Sized> Send for Cura<T> {}
-unsafe impl<T:  Send + Sync + ?Sized> Sync for Cura<T> {}
-
+unsafe impl<T:Send+Sync+?Sized,U:?Sized+Sync> Send for MappedReadGuard<'_,T,U> {}
+unsafe impl<T:Send+Sync+?Sized,U:?Sized+Sync> Sync for MappedReadGuard<'_,T,U> {}
 /**
- *  deref to make use simpler, this should also transparently
- *  read-lock
+ *  writeguard for a part of the value in a Cura
  */
-//TBD  feed this to chatgpt
-/*
-impl<T:Sync+Send> Deref for Cura<T>
+#[must_use = "if unused the Lock will immediately unlock"]
+#[clippy::has_significant_drop]
+pub struct MappedGuard<'a,T:Send+Sync+?Sized,U:?Sized>
 {
-    type Target = ReadGuard<T>;
-    //TBD this should probably return a reference to readguard?
-    fn deref(&self) -> &Self::Target {
-        todo!("this deref should actually do a 'read()' ");
-        //&self.data().data
-        &self.read()
+    cura:&'a Cura<T>,
+    data:*mut U, //invariant like &mut U
+    taken:Taken,
+}
+impl<'a,T:Send+Sync+?Sized,U:?Sized> MappedGuard<'a,T,U>
+{
+    ///
+    /// project further into the value
+    ///
+    pub fn map<V:?Sized+'a>(self,f:impl FnOnce(&mut U)->&mut V)->MappedGuard<'a,T,V>
+    {
+        let mut s=self;
+        let data:*mut V=f(&mut *s);
+        let cura=s.cura;
+        let taken=s.taken;
+        std::mem::forget(s);
+        MappedGuard{
+            cura,
+            data,
+            taken,
+        }
     }
-}*/
-/**
- *  clone to make new references of the object
- */
-impl<T:  Sync + Send +?Sized> Clone for Cura<T> {
-    fn clone(&self) -> Self {
-        self.data().count.fetch_add(1, Relaxed);
-        Cura {
-            ptr: self.ptr,
-            phantom:PhantomData,
-            //dummy:0,
-            }
+    ///
+    /// project further into the value, giving the guard back
+    /// along with the error if that fails
+    ///
+    pub fn try_map<V:?Sized+'a,E>(self,f:impl FnOnce(&mut U)->Result<&mut V,E>)
+        ->Result<MappedGuard<'a,T,V>,(Self,E)>
+    {
+        let mut s=self;
+        let data=match f(&mut *s) {
+            Ok(data)=>data as *mut V,
+            Err(e)=>{return Err((s,e));},
+        };
+        let cura=s.cura;
+        let taken=s.taken;
+        std::mem::forget(s);
+        Ok(MappedGuard{
+            cura,
+            data,
+            taken,
+        })
+    }
+    ///
+    /// project further into the value, giving the guard back
+    /// if there is nothing there
+    ///
+    pub fn filter_map<V:?Sized+'a>(self,f:impl FnOnce(&mut U)->Option<&mut V>)
+        ->Result<MappedGuard<'a,T,V>,Self>
+    {
+        self.try_map(|u|f(u).ok_or(())).map_err(|(s,_)|s)
     }
 }
-/**
- *  drop to clean up references
- */
-impl<T:  Sync + Send + ?Sized> Drop for Cura<T> {
+impl<T:Send+Sync+?Sized,U:?Sized> Drop for MappedGuard<'_,T,U>
+{
     fn drop(&mut self) {
-        if self.data().count.fetch_sub(1, Release) == 1 {
-            unsafe {
-                drop(Box::from_raw(self.ptr.as_ptr()));
-            }
+        self.cura.unwritelock(self.taken);
+    }
+}
+impl<T: Sync + Send + ?Sized,U:?Sized> Deref for MappedGuard<'_,T,U> {
+    type Target = U;
+    fn deref(&self) -> &U {
+        unsafe{
+            &*self.data
         }
     }
 }
-/**********************************************************
- *  guards
+impl<T: Sync + Send + ?Sized,U:?Sized> DerefMut for MappedGuard<'_,T,U> {
+    fn deref_mut(&mut self) -> &mut U {
+        unsafe{
+            &mut *self.data
+        }
+    }
+}
+unsafe impl<T:Send+Sync+?Sized,U:?Sized+Send> Send for MappedGuard<'_,T,U> {}
+unsafe impl<T:Send+Sync+?Sized,U:?Sized+Sync> Sync for MappedGuard<'_,T,U> {}
+/**
+ *  writeguard for Cura that keeps the Cura alive by itself
  */
-///
-/// writeguard for Cura
-///
 #[must_use = "if unused the Lock will immediately unlock"]
 #[clippy::has_significant_drop]
-pub struct Guard<'a,T: Send+Sync+?Sized>
+pub struct OwnedGuard<T:Send+Sync+?Sized>
 {
-    cura:&'a Cura<T>,
+    cura:Cura<T>,
+    taken:Taken,
 }
-impl<T:Send+Sync+?Sized> Drop for Guard<'_,T>
+impl<T:Send+Sync+?Sized> OwnedGuard<T>
+{
+    ///
+    /// the Cura this guard locks
+    ///
+    pub fn cura(&self)->&Cura<T>
+    {
+        &self.cura
+    }
+}
+impl<T:Send+Sync+?Sized> Drop for OwnedGuard<T>
 {
     fn drop(&mut self) {
-        self.cura.unwritelock(); //TBD no need to do anything else?
+        self.cura.unwritelock(self.taken);
     }
 }
-impl<T: Sync + Send+?Sized> Deref for Guard<'_,T> {
+impl<T: Sync + Send+?Sized> Deref for OwnedGuard<T> {
     type Target = T;
-    fn deref(&self) -> &T { //TBD reference lifetime?
+    fn deref(&self) -> &T {
         unsafe{
             &*self.cura.data().data.get()
         }
     }
 }
-impl<T: Sync + Send + ?Sized> DerefMut for Guard<'_,T> {
-    fn deref_mut(&mut self) -> &mut T { //TBD reference lifetime?
+impl<T: Sync + Send + ?Sized> DerefMut for OwnedGuard<T> {
+    fn deref_mut(&mut self) -> &mut T {
         unsafe {
             &mut *self.cura.data().data.get()
         }
     }
 }
-
-
 /**
- *  readguard for Cura
+ *  readguard for Cura that keeps the Cura alive by itself
  */
 #[must_use = "if unused the Lock will immediately unlock"]
 #[clippy::has_significant_drop]
-pub struct ReadGuard<'a,T:Send+Sync+?Sized>
+pub struct OwnedReadGuard<T:Send+Sync+?Sized>
 {
-    cura:&'a Cura<T>,
+    cura:Cura<T>,
 }
-impl<T:Send+Sync+?Sized> Drop for ReadGuard<'_,T>
+impl<T:Send+Sync+?Sized> OwnedReadGuard<T>
+{
+    ///
+    /// the Cura this guard locks
+    ///
+    pub fn cura(&self)->&Cura<T>
+    {
+        &self.cura
+    }
+}
+impl<T:Send+Sync+?Sized> Drop for OwnedReadGuard<T>
 {
     fn drop(&mut self) {
-        self.cura.unreadlock(); //TBD nothing else?
+        self.cura.unreadlock(Taken::unowned());
     }
 }
-impl<T: Sync + Send + ?Sized> Deref for ReadGuard<'_,T> {
+impl<T: Sync + Send + ?Sized> Deref for OwnedReadGuard<T> {
     type Target = T;
     fn deref(&self) -> &T {
         unsafe{
@@ -663,18 +4014,45 @@ impl<T: Sync + Send + ?Sized> Deref for ReadGuard<'_,T> {
         }
     }
 }
-///
-/// util to get current time in millis for testing
-///
-fn current_time()->u128{
-    SystemTime::now().
-        duration_since(UNIX_EPOCH).
-        expect("weird shit happened").
-        as_millis()
+impl<T: Sync + Send + ?Sized + std::fmt::Debug> std::fmt::Debug for Guard<'_,T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        std::fmt::Debug::fmt(&**self, f)
+    }
+}
+impl<T: Sync + Send + ?Sized + std::fmt::Debug> std::fmt::Debug for ReadGuard<'_,T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        std::fmt::Debug::fmt(&**self, f)
+    }
+}
+impl<T: Sync + Send + ?Sized + std::fmt::Debug> std::fmt::Debug for UpgradableReadGuard<'_,T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        std::fmt::Debug::fmt(&**self, f)
+    }
+}
+impl<T: Sync + Send + ?Sized,U:?Sized+std::fmt::Debug> std::fmt::Debug for MappedReadGuard<'_,T,U> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        std::fmt::Debug::fmt(&**self, f)
+    }
+}
+impl<T: Sync + Send + ?Sized,U:?Sized+std::fmt::Debug> std::fmt::Debug for MappedGuard<'_,T,U> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        std::fmt::Debug::fmt(&**self, f)
+    }
+}
+impl<T: Sync + Send + ?Sized + std::fmt::Debug> std::fmt::Debug for OwnedGuard<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        std::fmt::Debug::fmt(&**self, f)
+    }
+}
+impl<T: Sync + Send + ?Sized + std::fmt::Debug> std::fmt::Debug for OwnedReadGuard<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        std::fmt::Debug::fmt(&**self, f)
+    }
 }
 ///
 /// util to sĺeep for a few millis
 ///
+#[cfg(test)]
 fn sleep(millis:u32){
     std::thread::sleep(std::time::Duration::from_millis(millis.into()));
 }
@@ -684,6 +4062,7 @@ fn sleep(millis:u32){
 mod tests {
     use super::*;
     #[test]
+    #[allow(clippy::redundant_field_names)]
     fn basic_usecases() {
 
         /*  calculator for hits to "testing"*/
@@ -719,6 +4098,7 @@ mod tests {
         assert_eq!(21, NUM_HITS.load(Acquire));
     }
     #[test]
+    #[allow(dead_code,clippy::redundant_field_names,clippy::redundant_locals)]
     fn advanced_usecases()
     {
         static STATE:AtomicUsize=AtomicUsize::new(0);
@@ -776,81 +4156,944 @@ mod tests {
                         }
                     }
                 }
-            });
-            threads.push(t);
-        }
-        sleep(1000);
-        drop(w);
-        while let Some(t) = threads.pop()
-        {
-            t.join().unwrap();
-            println!("joined");
+            });
+            threads.push(t);
+        }
+        sleep(1000);
+        drop(w);
+        while let Some(t) = threads.pop()
+        {
+            t.join().unwrap();
+            println!("joined");
+        }
+
+        //let end=current_time();
+        //println!("took:{}",(end-start));
+    }
+    #[test]
+    #[allow(dead_code,unused_variables,clippy::redundant_field_names,clippy::needless_return)]
+    fn sized()
+    {
+        use std::sync::Arc;
+        fn test<T>(t:T)
+        {
+            println!("got t");
+        }
+        #[derive(Clone,Copy)]
+        enum EE
+        {
+            Bing,
+            Bong,
+        }
+        trait Foo:Send+Sync
+        {
+            fn get(&self)->EE
+            {
+                return EE::Bing;
+            }
+        }
+        struct FF;
+        impl Foo for FF{}
+
+        let t:Cura<dyn Foo>=Cura::from_box(Box::new(FF{}));
+        test(t);
+        let tt:Cura<dyn Foo>=Cura::from_box(Box::new(FF{}));
+        struct Bar<T:Sync+Send+?Sized>
+        {
+            tt:Cura<T>,
+        }
+        let f=Bar{tt:tt};
+        let _ = std::mem::size_of::<Cura<dyn Foo>>();
+        let _ = std::mem::size_of::<Arc<Arc<dyn Foo>>>();
+        let _ = std::mem::size_of::<Cura<Cura<dyn Foo>>>();
+        let _ = std::mem::size_of::<Bar<Cura<dyn Foo>>>();
+    }
+    #[test]
+    fn alter_works()
+    {
+        let t=Cura::new(3);
+        t.alter(|x|{
+            if *x==2{
+                *x=3;
+                Some(())
+            }else{
+                None
+            }
+        });
+
+        t.alter(|x|{
+            if *x==3{
+                *x=4;
+                Some(())
+            }else{
+                None
+            }
+        });
+
+    }
+    #[test]
+    fn update_and_with()
+    {
+        let t=Cura::new(Vec::new());
+        let mut threads=Vec::new();
+        for i in 0..10 {
+            let c=t.clone();
+            threads.push(std::thread::spawn(move||{
+                /*  captures i and returns a value from under the lock*/
+                c.update(|v|{
+                    v.push(i);
+                    v.len()
+                })
+            }));
+        }
+        let mut lens:Vec<usize>=threads.into_iter().map(|t|t.join().unwrap()).collect();
+        lens.sort();
+        assert_eq!(lens,(1..=10).collect::<Vec<_>>());
+        assert_eq!(t.with(|v|v.iter().sum::<i32>()),45);
+        let limit=10;
+        let res=t.try_update(|v|{
+            if v.len()>=limit {
+                Err(v.len())
+            }else{
+                v.push(0);
+                Ok(())
+            }
+        });
+        assert_eq!(res,Err(10));
+        /*  alter still works*/
+        t.alter(|v|{
+            v.clear();
+            Some(())
+        });
+        assert!(t.with(|v|v.is_empty()));
+    }
+    #[test]
+    fn replacing_values()
+    {
+        static DROPS: AtomicUsize = AtomicUsize::new(0);
+        #[derive(Default,Debug,PartialEq)]
+        struct Foo(i32);
+        impl Drop for Foo {
+            fn drop(&mut self) {
+                DROPS.fetch_add(1, Relaxed);
+            }
+        }
+        let a=Cura::new(Foo(1));
+        let b=Cura::new(Foo(2));
+        assert_eq!(a.replace(Foo(3)).0,1);
+        assert_eq!(DROPS.load(Relaxed),1);
+        a.set(Foo(4));
+        assert_eq!(DROPS.load(Relaxed),2);
+        assert_eq!(a.take().0,4);
+        assert_eq!(a.read().0,0);
+        /*  swapping both ways from several threads doesnt deadlock*/
+        let mut threads=Vec::new();
+        for i in 0..4 {
+            let (a,b)=(a.clone(),b.clone());
+            threads.push(std::thread::spawn(move||{
+                for _ in 0..501 {
+                    if i%2==0 {
+                        a.swap(&b);
+                    }else{
+                        b.swap(&a);
+                    }
+                }
+            }));
+        }
+        while let Some(t)=threads.pop()
+        {
+            t.join().unwrap();
+        }
+        a.swap(&a);
+        assert_eq!((a.read().0,b.read().0),(0,2));
+        let drops=DROPS.load(Relaxed);
+        /*  unwrapping only works on the last reference*/
+        let a2=a.clone();
+        let w=a.downgrade();
+        let a=Cura::try_unwrap(a).unwrap_err();
+        assert_eq!(Cura::into_inner(a2),None);
+        let foo=Cura::try_unwrap(a).ok().unwrap();
+        assert_eq!(foo.0,0);
+        assert!(w.upgrade().is_none());
+        assert_eq!(DROPS.load(Relaxed),drops);
+        drop(foo);
+        assert_eq!(Cura::into_inner(b).unwrap().0,2);
+        assert_eq!(DROPS.load(Relaxed),drops+2);
+    }
+    #[test]
+    fn poisoning()
+    {
+        let s=Cura::new(vec![1]);
+        assert!(!s.is_poisoned());
+        /*  a reader panicking doesnt poison anything*/
+        let c=s.clone();
+        assert!(std::thread::spawn(move||{
+            let _r=c.read();
+            panic!("reader panic");
+        }).join().is_err());
+        assert!(!s.is_poisoned());
+        /*  a writer panicking halfway does*/
+        let c=s.clone();
+        assert!(std::thread::spawn(move||{
+            let mut w=c.write();
+            w.push(2);
+            panic!("writer panic");
+        }).join().is_err());
+        assert!(s.is_poisoned());
+        assert!(s.read_checked().is_err());
+        /*  the data is still reachable*/
+        {
+            let mut w=s.write_checked().unwrap_err().into_inner();
+            w.pop();
+        }
+        assert_eq!(*s.read(),vec![1]);
+        s.clear_poison();
+        assert!(s.write_checked().is_ok());
+        assert!(s.read_checked().is_ok());
+        /*  a lock taken while already unwinding isnt poisoned by that panic*/
+        struct Cleanup(Cura<Vec<i32>>);
+        impl Drop for Cleanup
+        {
+            fn drop(&mut self)
+            {
+                let mut w=self.0.write();
+                w.push(3);
+                w.pop();
+            }
+        }
+        let c=s.clone();
+        assert!(std::thread::spawn(move||{
+            let _cleanup=Cleanup(c);
+            panic!("unrelated panic");
+        }).join().is_err());
+        assert!(!s.is_poisoned());
+        /*  and handing the lock over to a waiter still poisons it*/
+        struct Fair<'a>(Option<Guard<'a,Vec<i32>>>);
+        struct Join(Option<std::thread::JoinHandle<usize>>);
+        impl Drop for Join
+        {
+            fn drop(&mut self)
+            {
+                let _=self.0.take().unwrap().join();
+            }
+        }
+        impl Drop for Fair<'_>
+        {
+            fn drop(&mut self)
+            {
+                self.0.take().unwrap().unlock_fair();
+            }
+        }
+        let c=s.clone();
+        assert!(std::thread::spawn(move||{
+            //  joined after the lock is handed to it
+            let mut waiter=Join(None);
+            let _w=Fair(Some(c.write()));
+            let c2=c.clone();
+            waiter.0=Some(std::thread::spawn(move||{
+                c2.write().len()
+            }));
+            wait_for_queue(&c,1);
+            panic!("writer panic");
+        }).join().is_err());
+        assert!(s.is_poisoned());
+        assert_eq!(*s.read(),vec![1]);
+    }
+    ///
+    /// tiny executor that parks the thread until woken
+    ///
+    fn block_on<F:Future>(f:F)->F::Output
+    {
+        use std::sync::Arc;
+        use std::task::Wake;
+        struct ThreadWaker(Thread);
+        impl Wake for ThreadWaker {
+            fn wake(self:Arc<Self>) {
+                self.0.unpark();
+            }
+        }
+        let waker=Waker::from(Arc::new(ThreadWaker(std::thread::current())));
+        let mut cx=Context::from_waker(&waker);
+        let mut f=Box::pin(f);
+        loop{
+            match f.as_mut().poll(&mut cx) {
+                Poll::Ready(v)=>{return v;},
+                Poll::Pending=>std::thread::park(),
+            }
+        }
+    }
+    ///
+    /// waker that counts how many times it was woken
+    ///
+    fn counting_waker()->(Waker,std::sync::Arc<AtomicUsize>)
+    {
+        use std::sync::Arc;
+        use std::task::Wake;
+        struct Counter(Arc<AtomicUsize>);
+        impl Wake for Counter {
+            fn wake(self:Arc<Self>) {
+                self.0.fetch_add(1,SeqCst);
+            }
+        }
+        let count=Arc::new(AtomicUsize::new(0));
+        (Waker::from(Arc::new(Counter(count.clone()))),count)
+    }
+    #[test]
+    fn async_locks()
+    {
+        let s=Cura::new(0);
+        let mut w=s.write();
+        let mut threads=Vec::new();
+        for i in 0..10 {
+            let c=s.clone();
+            threads.push(std::thread::spawn(move||{
+                block_on(async{
+                    if i%2==0 {
+                        *c.write_async().await+=1;
+                    }else{
+                        let r=c.read_async().await;
+                        assert!(*r>=100);
+                    }
+                });
+            }));
+        }
+        sleep(100);
+        assert!(s.waiter_count()>0);
+        *w=100;
+        drop(w);
+        while let Some(t)=threads.pop()
+        {
+            t.join().unwrap();
+        }
+        assert_eq!(*s.read(),105);
+        assert_eq!(s.waiter_count(),0);
+    }
+    #[test]
+    fn async_cancellation()
+    {
+        let s=Cura::new(0);
+        let (waker,wakes)=counting_waker();
+        let mut cx=Context::from_waker(&waker);
+        let w=s.write();
+        /*  two queued futures*/
+        let mut first=Box::pin(s.write_async());
+        let mut second=Box::pin(s.read_async());
+        assert!(first.as_mut().poll(&mut cx).is_pending());
+        assert!(second.as_mut().poll(&mut cx).is_pending());
+        assert_eq!(s.waiter_count(),2);
+        /*  polling again while not first changes nothing*/
+        assert!(second.as_mut().poll(&mut cx).is_pending());
+        assert_eq!(s.waiter_count(),2);
+        drop(w);
+        /*  first is woken but cancelled, the wakeup goes on to second*/
+        let woken=wakes.load(SeqCst);
+        assert!(woken>=1);
+        drop(first);
+        assert!(wakes.load(SeqCst)>woken);
+        assert_eq!(s.waiter_count(),1);
+        match second.as_mut().poll(&mut cx) {
+            Poll::Ready(r)=>assert_eq!(*r,0),
+            Poll::Pending=>panic!("should have gotten the lock"),
+        }
+        assert_eq!(s.waiter_count(),0);
+        /*  cancelling something never polled is fine too*/
+        drop(s.write_async());
+        assert!(s.try_write().is_some());
+        /*  a lock handed to a future that is then dropped is passed on*/
+        let w=s.write();
+        let mut first=Box::pin(s.write_async());
+        let mut second=Box::pin(s.write_async());
+        assert!(first.as_mut().poll(&mut cx).is_pending());
+        assert!(second.as_mut().poll(&mut cx).is_pending());
+        w.unlock_fair();
+        assert!(s.try_read().is_none());
+        drop(first);
+        assert!(s.try_read().is_none());
+        match second.as_mut().poll(&mut cx) {
+            Poll::Ready(mut w)=>*w+=1,
+            Poll::Pending=>panic!("should have been handed the lock"),
+        }
+        assert_eq!(*s.read(),1);
+        assert_eq!(s.waiter_count(),0);
+    }
+    #[test]
+    fn wakers_outside_queue_lock()
+    {
+        //  a waker that looks into the queue itself, which would
+        //  spin forever if it was woken with the queue locked
+        use std::sync::{Arc,Mutex};
+        use std::task::Wake;
+        struct Peek(Mutex<Option<Cura<i32>>>,AtomicUsize);
+        impl Wake for Peek {
+            fn wake(self:Arc<Self>) {
+                self.wake_by_ref();
+            }
+            fn wake_by_ref(self:&Arc<Self>) {
+                if let Some(s)=&*self.0.lock().unwrap()
+                {
+                    s.waiter_count();
+                }
+                self.1.fetch_add(1,SeqCst);
+            }
+        }
+        let s=Cura::new(0);
+        let peek=Arc::new(Peek(Mutex::new(Some(s.clone())),AtomicUsize::new(0)));
+        let waker=Waker::from(peek.clone());
+        let mut cx=Context::from_waker(&waker);
+        let w=s.write();
+        let mut f=Box::pin(s.read_async());
+        assert!(f.as_mut().poll(&mut cx).is_pending());
+        drop(w);
+        assert_eq!(peek.1.load(SeqCst),1);
+        assert!(f.as_mut().poll(&mut cx).is_ready());
+        /*  same for a lock handed over*/
+        let w=s.write();
+        let mut f=Box::pin(s.write_async());
+        assert!(f.as_mut().poll(&mut cx).is_pending());
+        w.unlock_fair();
+        assert_eq!(peek.1.load(SeqCst),2);
+        assert!(f.as_mut().poll(&mut cx).is_ready());
+        *peek.0.lock().unwrap()=None;
+    }
+    #[test]
+    #[cfg(feature="deadlock_detection")]
+    #[should_panic(expected="deadlock detected")]
+    fn deadlock_on_self()
+    {
+        let s=Cura::new(1);
+        let _r=s.read();
+        let _w=s.write();
+    }
+    #[test]
+    #[cfg(feature="deadlock_detection")]
+    fn deadlock_between_threads()
+    {
+        use std::sync::{Arc,Barrier};
+        let a=Cura::new(1);
+        let b=Cura::new(2);
+        let barrier=Arc::new(Barrier::new(2));
+        let (a2,b2,barrier2)=(a.clone(),b.clone(),barrier.clone());
+        let t1=std::thread::Builder::new().name("first".into()).spawn(move||{
+            let _a=a2.write();
+            barrier2.wait();
+            let _b=b2.write();
+        }).unwrap();
+        let (a2,b2)=(a.clone(),b.clone());
+        let t2=std::thread::Builder::new().name("second".into()).spawn(move||{
+            let _b=b2.write();
+            barrier.wait();
+            sleep(200); //let first get stuck waiting for b
+            let _a=a2.write();
+        }).unwrap();
+        /*  second one closes the cycle and panics, which lets first go*/
+        let err=t2.join().unwrap_err();
+        let msg=err.downcast_ref::<String>().unwrap();
+        assert!(msg.contains("thread 'second' waits for"),"{}",msg);
+        assert!(msg.contains("thread 'first' waits for"),"{}",msg);
+        t1.join().unwrap();
+        /*  and everything is released*/
+        assert!(a.try_write().is_some());
+        assert!(b.try_write().is_some());
+    }
+    #[test]
+    #[cfg(feature="deadlock_detection")]
+    fn no_false_deadlocks()
+    {
+        /*  waiting on someone who isnt waiting on us is fine*/
+        let s=Cura::new(1);
+        let u=s.upgradable_read();
+        let c=s.clone();
+        let t=std::thread::spawn(move||{
+            let _r=c.read();
+            sleep(100);
+        });
+        sleep(20);
+        *u.upgrade()+=1;
+        t.join().unwrap();
+        let w=s.write();
+        let c=s.clone();
+        let t=std::thread::spawn(move||{
+            *c.write()+=1;
+        });
+        sleep(50);
+        drop(w);
+        t.join().unwrap();
+        assert_eq!(*s.read(),3);
+        /*  a lock taken by a task isnt held by the thread that polled it*/
+        let r=block_on(s.read_async());
+        let c=s.clone();
+        let (tx,rx)=std::sync::mpsc::channel();
+        std::thread::scope(|sc|{
+            sc.spawn(move||{
+                let _mine=c.read();
+                drop(r);
+                tx.send(()).unwrap();
+                sleep(50);
+            });
+            rx.recv().unwrap();
+            *s.write()+=1;
+        });
+        assert_eq!(*s.read(),4);
+    }
+    #[test]
+    fn sent_and_forgotten_guards()
+    {
+        /*  a guard the thread sent away and someone else dropped is no
+         *  longer the thread's to wait on*/
+        let s=Cura::new(4);
+        let contended=|s:&Cura<i32>|{
+            let c=s.clone();
+            let (tx,rx)=std::sync::mpsc::channel();
+            let t=std::thread::spawn(move||{
+                let _r=c.read();
+                tx.send(()).unwrap();
+                sleep(50);
+            });
+            rx.recv().unwrap();
+            *s.write()+=1;
+            t.join().unwrap();
+        };
+        let w=s.write();
+        std::thread::scope(|sc|{sc.spawn(move||drop(w));});
+        contended(&s);
+        assert_eq!(*s.read(),5);
+        /*  nor a forgotten one of a Cura since gone,whose address
+         *  may well be reused*/
+        let f=Cura::new(0);
+        let addr=f.addr();
+        std::mem::forget(f.write());
+        drop(f);
+        let reused:Vec<Cura<i32>>=(0..100).map(|_|Cura::new(0)).collect();
+        for r in reused.iter().filter(|r|r.addr()==addr) {
+            contended(r);
+        }
+    }
+    #[test]
+    #[cfg(not(feature="deadlock_detection"))]
+    fn waiting_for_a_sent_guard()
+    {
+        /*  a guard alive on another thread is that thread's to let go
+         *  of,so waiting for it is fine*/
+        let s=Cura::new(0);
+        let w=s.write();
+        std::thread::scope(|sc|{
+            sc.spawn(move||{
+                sleep(100);
+                drop(w);
+            });
+            *s.write()+=1;
+        });
+        assert_eq!(*s.read(),1);
+    }
+    #[test]
+    #[cfg(feature="deadlock_detection")]
+    #[should_panic(expected="already holds")]
+    fn write_while_reading()
+    {
+        let s=Cura::new(1);
+        let _r=s.read();
+        let _w=s.write();
+    }
+    #[test]
+    fn reentrant_reads()
+    {
+        let s=Cura::new(1);
+        /*  an upgrade waiting for us keeps new readers out,so a nested
+         *  read would wait for the upgrader which waits for us*/
+        let nested=|reentrant:bool|{
+            s.set_reentrant(reentrant);
+            let r=s.read();
+            let c=s.clone();
+            let t=std::thread::spawn(move||{
+                let u=c.upgradable_read();
+                *u.upgrade()+=1;
+            });
+            while s.state().load(SeqCst)&UPGRADING==0
+            {
+                std::thread::yield_now();
+            }
+            let res=std::panic::catch_unwind(std::panic::AssertUnwindSafe(||{
+                *s.read()
+            }));
+            drop(r);
+            t.join().unwrap();
+            res
+        };
+        let err=nested(false).unwrap_err();
+        let msg=err.downcast_ref::<String>().unwrap();
+        assert!(msg.contains("set_reentrant"),"{}",msg);
+        assert_eq!(nested(true).unwrap(),2);
+        assert_eq!(*s.read(),3);
+        assert_eq!(held::get(s.addr()).reads,0);
+    }
+    ///
+    /// spin until n threads are waiting in the queue of s
+    ///
+    fn wait_for_queue<T:Send+Sync>(s:&Cura<T>,n:usize)
+    {
+        while s.waiter_count()!=n
+        {
+            std::thread::yield_now();
+        }
+    }
+    #[test]
+    fn fifo_policy()
+    {
+        for _ in 0..5
+        {
+            let s=Cura::with_policy(0,Policy::Fifo);
+            let log=Cura::new(Vec::new());
+            let w=s.write();
+            //  a line of writers and runs of readers, in a known order
+            let mut handles=Vec::new();
+            for i in 0..16
+            {
+                let (c,log)=(s.clone(),log.clone());
+                handles.push(std::thread::spawn(move||{
+                    let s=c;
+                    if i%4==0 || i==15
+                    {
+                        let _w=s.write();
+                        log.write().push((i,true));
+                    }else{
+                        let _r=s.read();
+                        log.write().push((i,false));
+                    }
+                }));
+                wait_for_queue(&s,i+1);
+            }
+            //  newcomers hammering the lock may not get past the line
+            for i in 16..24
+            {
+                let (s,log)=(s.clone(),log.clone());
+                handles.push(std::thread::spawn(move||{
+                    for _ in 0..100
+                    {
+                        if i%2==0
+                        {
+                            *s.write()+=1;
+                        }else{
+                            assert!(*s.read()>=0);
+                        }
+                    }
+                    log.write().push((i,true));
+                }));
+            }
+            assert!(s.try_read().is_none());
+            drop(w);
+            for h in handles
+            {
+                h.join().unwrap();
+            }
+            let log=log.read();
+            for (n,&(a,awrote)) in log.iter().enumerate()
+            {
+                for &(b,bwrote) in &log[n+1..]
+                {
+                    //  only readers next to each other may mix, and
+                    //  the newcomers among themselves
+                    if (awrote || bwrote) && a.min(b)<16
+                    {
+                        assert!(a<b,"{:?}",*log);
+                    }
+                }
+            }
         }
-
-        //let end=current_time();
-        //println!("took:{}",(end-start));
+        /*  readers woken out of the queue that have yet to come for
+         *  the lock are nobody a nested read has to wait behind*/
+        let s=Cura::with_policy(0,Policy::Fifo);
+        let r=s.read();
+        s.state().fetch_add(PARKED|WAKEUP,SeqCst);
+        assert_eq!(*s.read(),0);
+        s.state().fetch_sub(PARKED|WAKEUP,SeqCst);
+        drop(r);
+        assert!(s.try_write().is_some());
     }
     #[test]
-    fn sized()
+    fn writer_preferring_policy()
     {
-        use std::sync::Arc;
-        fn test<T>(t:T)
+        for _ in 0..5
         {
-            println!("got t");
+            let s=Cura::with_policy(0,Policy::WriterPreferring);
+            let r=s.read();
+            let c=s.clone();
+            let writer=std::thread::spawn(move||{
+                *c.write()=1;
+            });
+            wait_for_queue(&s,1);
+            //  no reader that comes after a waiting writer gets in first
+            let readers:Vec<_>=(0..8).map(|_|{
+                let s=s.clone();
+                std::thread::spawn(move||{
+                    for _ in 0..100
+                    {
+                        assert_eq!(*s.read(),1);
+                    }
+                })
+            }).collect();
+            assert!(s.try_read().is_none());
+            //  queued writers go before queued readers
+            wait_for_queue(&s,9);
+            let c=s.clone();
+            let writer2=std::thread::spawn(move||{
+                let mut w=c.write();
+                assert_eq!(*w,1);
+                *w=1;
+            });
+            wait_for_queue(&s,10);
+            drop(r);
+            writer.join().unwrap();
+            writer2.join().unwrap();
+            for h in readers
+            {
+                h.join().unwrap();
+            }
         }
-        #[derive(Clone,Copy)]
-        enum EE
+        //  and writers get through a constant stream of readers
+        let s=Cura::with_policy(0,Policy::WriterPreferring);
+        let done=Cura::new(false);
+        let readers:Vec<_>=(0..4).map(|_|{
+            let (s,done)=(s.clone(),done.clone());
+            std::thread::spawn(move||{
+                while !*done.read()
+                {
+                    let _r=s.read();
+                    std::hint::spin_loop();
+                }
+            })
+        }).collect();
+        for _ in 0..100
         {
-            Bing,
-            Bong,
+            *s.write()+=1;
         }
-        trait Foo:Send+Sync
+        *done.write()=true;
+        for h in readers
         {
-            fn get(&self)->EE
+            h.join().unwrap();
+        }
+        assert_eq!(*s.read(),100);
+    }
+    #[test]
+    fn reader_preferring_policy()
+    {
+        for _ in 0..5
+        {
+            let s=Cura::with_policy(0,Policy::ReaderPreferring);
+            let r=s.read();
+            let c=s.clone();
+            let writer=std::thread::spawn(move||{
+                *c.write()=1;
+            });
+            wait_for_queue(&s,1);
+            //  readers dont wait for a waiting writer at all
+            let readers:Vec<_>=(0..8).map(|_|{
+                let s=s.clone();
+                std::thread::spawn(move||{
+                    for _ in 0..100
+                    {
+                        assert_eq!(*s.read(),0);
+                    }
+                })
+            }).collect();
+            for h in readers
             {
-                return EE::Bing;
+                h.join().unwrap();
             }
+            assert!(s.try_read().is_some());
+            drop(r);
+            writer.join().unwrap();
+            assert_eq!(*s.read(),1);
         }
-        struct FF;
-        impl Foo for FF{}
-
-        let t:Cura<dyn Foo>=Cura::from_box(Box::new(FF{}));
-        test(t);
-        let tt:Cura<dyn Foo>=Cura::from_box(Box::new(FF{}));
-        struct Bar<T:Sync+Send+?Sized>
+        //  readers queued behind a writelock go before queued writers
+        let s=Cura::with_policy(Vec::new(),Policy::ReaderPreferring);
+        let w=s.write();
+        let c=s.clone();
+        let writer=std::thread::spawn(move||{
+            c.write().push("writer");
+        });
+        wait_for_queue(&s,1);
+        let c=s.clone();
+        let reader=std::thread::spawn(move||{
+            assert!(c.read().is_empty());
+        });
+        wait_for_queue(&s,2);
+        drop(w);
+        reader.join().unwrap();
+        writer.join().unwrap();
+        assert_eq!(*s.read(),vec!["writer"]);
+    }
+    #[test]
+    fn spin_policies()
+    {
+        let policies=[SpinPolicy::Spin(0),SpinPolicy::Spin(1000),SpinPolicy::Backoff(20),
+                      SpinPolicy::Yield(3),SpinPolicy::Park];
+        for policy in policies
         {
-            tt:Cura<T>,
+            let s=Cura::new(0);
+            s.set_spin_policy(Some(policy));
+            assert_eq!(s.spin_policy(),policy);
+            let handles:Vec<_>=(0..4).map(|i|{
+                let s=s.clone();
+                std::thread::spawn(move||{
+                    for _ in 0..200
+                    {
+                        if i%2==0
+                        {
+                            *s.write()+=1;
+                        }else{
+                            let u=s.upgradable_read();
+                            let r=s.read();
+                            assert_eq!(*r,*u);
+                            drop(r);
+                            *u.upgrade()+=1;
+                        }
+                    }
+                })
+            }).collect();
+            for h in handles
+            {
+                h.join().unwrap();
+            }
+            assert_eq!(*s.read(),800);
         }
-        let f=Bar{tt:tt};
-        let _ = std::mem::size_of::<Cura<dyn Foo>>();
-        let _ = std::mem::size_of::<Arc<Arc<dyn Foo>>>();
-        let _ = std::mem::size_of::<Cura<Cura<dyn Foo>>>();
-        let _ = std::mem::size_of::<Bar<Cura<dyn Foo>>>();
+        //  the default follows the global one unless set
+        let s=Cura::new(0);
+        assert_eq!(s.spin_policy(),default_spin_policy());
+        s.set_spin_policy(Some(SpinPolicy::Park));
+        s.set_spin_policy(None);
+        assert_eq!(s.spin_policy(),default_spin_policy());
+        //  and counts are cut down to what fits, leaving the policy be
+        let s=Cura::with_policy(0,Policy::Fifo);
+        s.set_spin_policy(Some(SpinPolicy::Spin(u32::MAX)));
+        assert_eq!(s.spin_policy(),SpinPolicy::Spin((1<<SPINCOUNTBITS)-1));
+        assert_eq!(s.policy(),Policy::Fifo);
     }
     #[test]
-    fn alter_works()
+    fn batch_wakeup()
     {
-        let t=Cura::new(3);
-        t.alter(|x|{
-            if *x==2{
-                *x=3;
-                Some(())
-            }else{
-                None
+        for _ in 0..5
+        {
+            let s=Cura::new(0);
+            let mut w=s.write();
+            let all_in=std::sync::Arc::new(std::sync::Barrier::new(4));
+            let mut handles:Vec<_>=(0..4).map(|i|{
+                let (c,all_in)=(s.clone(),all_in.clone());
+                let h=std::thread::spawn(move||{
+                    let r=c.read();
+                    //  everyone reads at the same time
+                    all_in.wait();
+                    assert_eq!(*r,1);
+                });
+                wait_for_queue(&s,i+1);
+                h
+            }).collect();
+            let c=s.clone();
+            handles.push(std::thread::spawn(move||{
+                *c.write()+=1;
+            }));
+            wait_for_queue(&s,5);
+            *w=1;
+            drop(w);
+            //  the release took all of the readers out in one go
+            let qdata=s.lock_queue();
+            let first=qdata.queue;
+            assert!(first.is_null() || unsafe{(*first).lock==LockType::Write});
+            s.unlock_queue(qdata);
+            for h in handles
+            {
+                h.join().unwrap();
             }
+            assert_eq!(*s.read(),2);
+        }
+    }
+    #[test]
+    fn fair_unlocking()
+    {
+        //  a waiting writer gets the lock before we can grab it back
+        let s=Cura::new(0);
+        let w=s.write();
+        let c=s.clone();
+        let h=std::thread::spawn(move||{
+            let mut w=c.write();
+            sleep(10);
+            *w+=1;
         });
+        wait_for_queue(&s,1);
+        w.unlock_fair();
+        assert!(s.try_write().is_none());
+        h.join().unwrap();
+        assert_eq!(*s.read(),1);
 
-        t.alter(|x|{
-            if *x==3{
-                *x=4;
-                Some(())
-            }else{
-                None
-            }
+        //  same for the last reader, and the whole run of readers
+        //  a writer leaves behind
+        let r=s.read();
+        let c=s.clone();
+        let h=std::thread::spawn(move||{
+            let mut w=c.write();
+            sleep(10);
+            *w+=1;
+        });
+        wait_for_queue(&s,1);
+        r.unlock_fair();
+        assert!(s.try_read().is_none());
+        h.join().unwrap();
+        let w=s.write();
+        let all_in=std::sync::Arc::new(std::sync::Barrier::new(3));
+        let handles:Vec<_>=(0..3).map(|i|{
+            let (c,all_in)=(s.clone(),all_in.clone());
+            let h=std::thread::spawn(move||{
+                let r=c.read();
+                all_in.wait();
+                assert_eq!(*r,2);
+            });
+            wait_for_queue(&s,i+1);
+            h
+        }).collect();
+        w.unlock_fair();
+        assert!(s.try_write().is_none());
+        for h in handles
+        {
+            h.join().unwrap();
+        }
+
+        //  bump lets the waiter in between
+        let mut w=s.write();
+        let c=s.clone();
+        let h=std::thread::spawn(move||{
+            *c.write()+=1;
         });
+        wait_for_queue(&s,1);
+        w.bump();
+        assert_eq!(*w,3);
+        w.bump();
+        drop(w);
+        h.join().unwrap();
 
+        //  unlocked lets others in and always takes the lock back
+        let mut w=s.write();
+        w.unlocked(||{
+            *s.write()+=1;
+        });
+        assert_eq!(*w,4);
+        let panicked=std::panic::catch_unwind(std::panic::AssertUnwindSafe(||{
+            w.unlocked(||{
+                panic!("while unlocked");
+            });
+        }));
+        assert!(panicked.is_err());
+        assert!(s.try_read().is_none());
+        *w+=1;
+        drop(w);
+        let mut r=s.read();
+        let v=r.unlocked(||{
+            *s.write()+=1;
+            1
+        });
+        assert_eq!(*r,5+v);
+        assert!(!s.is_poisoned());
     }
     #[test]
+    #[allow(dead_code,clippy::clone_on_copy,clippy::assign_op_pattern)]
     fn loop_a_lot()
     {
         #[derive(Clone,Copy)]
@@ -873,6 +5116,7 @@ mod tests {
         }
     }
     #[test]
+    #[allow(dead_code,redundant_semicolons,clippy::needless_return,clippy::clone_on_copy,clippy::assign_op_pattern)]
     fn loop_a_lot_box()
     {
         #[derive(Clone,Copy)]
@@ -906,6 +5150,214 @@ mod tests {
         }
     }
     #[test]
+    fn compact_layout()
+    {
+        //  one allocation with the value right behind the header
+        assert_eq!(std::mem::size_of::<CuraData<u8>>(),
+                   std::mem::size_of::<Header>()+std::mem::align_of::<Header>());
+        //  which is two counts, the config and state words and the queue head
+        assert!(std::mem::size_of::<CuraData<u64>>()<=40);
+        let s=Cura::new(5u8);
+        let p=&*s.read() as *const u8 as usize;
+        assert_eq!(p-s.ptr.as_ptr() as *const u8 as usize,std::mem::size_of::<Header>());
+
+        //  unsized values get moved in too, and dropped from there
+        let v:Cura<[u16]>=Cura::from_box(vec![1,2,3].into_boxed_slice());
+        v.write()[1]=7;
+        assert_eq!(&*v.read(),&[1,7,3]);
+        let st:Cura<str>=Cura::from_box("hello".into());
+        assert_eq!(&*st.read(),"hello");
+        let dropped=std::sync::Arc::new(AtomicUsize::new(0));
+        struct D(std::sync::Arc<AtomicUsize>,u64);
+        impl Drop for D
+        {
+            fn drop(&mut self)
+            {
+                self.0.fetch_add(self.1 as usize,SeqCst);
+            }
+        }
+        trait Foo:Send+Sync{}
+        impl Foo for D{}
+        let d:Cura<dyn Foo>=Cura::from_box(Box::new(D(dropped.clone(),1)));
+        let w=d.downgrade();
+        drop(d);
+        assert_eq!(dropped.load(SeqCst),1);
+        assert!(w.upgrade().is_none());
+
+        //  and the state word keeps track of everything
+        let s=Cura::new(1);
+        {
+            let _r1=s.read();
+            let _r2=s.read();
+            assert_eq!(s.state().load(SeqCst)&READERS,2*READER);
+        }
+        //  a full reader count keeps readers out rather than blowing up
+        s.state().fetch_or(READERS,SeqCst);
+        assert!(s.try_read().is_none());
+        assert!(s.try_upgradable_read().is_none());
+        s.state().fetch_and(!READERS,SeqCst);
+        assert!(s.try_read().is_some());
+        {
+            let _w=s.write();
+            assert_eq!(s.state().load(SeqCst),WRITER);
+        }
+        assert_eq!(s.state().load(SeqCst),0);
+    }
+    #[test]
+    fn conversions()
+    {
+        trait Foo:Send+Sync
+        {
+            fn get(&self)->u64;
+            fn set(&mut self,i:u64);
+        }
+        struct FF(u64);
+        impl Foo for FF
+        {
+            fn get(&self)->u64{self.0}
+            fn set(&mut self,i:u64){self.0=i;}
+        }
+        let a:Cura<u64>=5.into();
+        assert_eq!(*a.read(),5);
+        let b:Cura<dyn Foo>=Cura::from(Box::new(FF(1)) as Box<dyn Foo>);
+        assert_eq!(b.read().get(),1);
+
+        //  unsizing keeps the value, lock and counts shared
+        let c=Cura::new(FF(2));
+        let w=c.downgrade();
+        let d:Cura<dyn Foo>=unsize!(c.clone(),dyn Foo);
+        d.write().set(3);
+        assert_eq!(c.read().get(),3);
+        {
+            let _r=c.read();
+            assert!(d.try_write().is_none());
+        }
+        drop(c);
+        assert!(w.upgrade().is_some());
+        drop(d);
+        assert!(w.upgrade().is_none());
+
+        let s:Cura<[u8]>=unsize!(Cura::new([1u8,2,3]),[u8]);
+        assert_eq!(&*s.read(),&[1,2,3]);
+    }
+    #[test]
+    fn unsized_coercion()
+    {
+        trait Foo:Send+Sync
+        {
+            fn get(&self)->usize;
+        }
+        struct FF(usize,std::sync::Arc<AtomicUsize>);
+        impl Foo for FF
+        {
+            fn get(&self)->usize{self.0}
+        }
+        impl Drop for FF
+        {
+            fn drop(&mut self)
+            {
+                self.1.fetch_add(1,SeqCst);
+            }
+        }
+        let drops=std::sync::Arc::new(AtomicUsize::new(0));
+        let a=Cura::new(FF(1,drops.clone()));
+        let w=unsize!(a.downgrade(),dyn Foo);
+        let b=unsize!(a,dyn Foo);
+        let v:Vec<Cura<dyn Foo>>=vec![b.clone(),b,Cura::from_box(Box::new(FF(2,drops.clone())))];
+        assert_eq!(v.iter().map(|c|c.read().get()).sum::<usize>(),4);
+        assert_eq!(w.upgrade().unwrap().read().get(),1);
+        drop(v);
+        assert_eq!(drops.load(SeqCst),2);
+        //  the weak one only frees the memory now
+        assert!(w.upgrade().is_none());
+        drop(w);
+
+        #[cfg(feature="coerce_unsized")]
+        {
+            let c:Cura<dyn Foo>=Cura::new(FF(3,drops.clone()));
+            let w:WeakCura<dyn Foo>=c.downgrade();
+            assert_eq!(w.upgrade().unwrap().read().get(),3);
+        }
+    }
+    #[test]
+    fn owned_guards()
+    {
+        fn locked()->(Cura<Vec<i32>>,OwnedGuard<Vec<i32>>)
+        {
+            let s=Cura::new(vec![1]);
+            let w=s.write_owned();
+            (s,w)
+        }
+        let (s,mut w)=locked();
+        w.push(2);
+        assert!(s.try_read().is_none());
+        assert_eq!(s.strong_count(),2);
+        let t=std::thread::spawn(move||{
+            sleep(50);
+            w.push(3);
+            drop(w);
+        });
+        //  waiting for a lock sent away is not a deadlock
+        drop(s.write());
+        t.join().unwrap();
+        assert_eq!(*s.read(),vec![1,2,3]);
+        assert_eq!(s.strong_count(),1);
+
+        //  the guards outlive the Cura they came from
+        struct Holder{r:OwnedReadGuard<Vec<i32>>}
+        let h=Holder{r:s.read_owned()};
+        let r2=s.read_owned();
+        drop(s);
+        assert_eq!(h.r.len(),3);
+        assert!(h.r.cura().try_write().is_none());
+        let c=r2.cura().clone();
+        drop(h);
+        drop(r2);
+        assert!(c.try_write().is_some());
+    }
+    #[test]
+    fn introspection()
+    {
+        let s=Cura::new(1);
+        assert_eq!(s.lock_state(),LockState::Free);
+        assert!(!s.is_locked());
+        {
+            let _r=s.read();
+            let _u=s.upgradable_read();
+            assert_eq!(s.lock_state(),LockState::Read(2));
+            assert!(s.is_locked());
+        }
+        let w=s.write();
+        assert_eq!(s.lock_state(),LockState::Write);
+        let handles:Vec<_>=(0..3).map(|i|{
+            let c=s.clone();
+            let h=std::thread::spawn(move||{
+                *c.write()+=1;
+            });
+            wait_for_queue(&s,i+1);
+            h
+        }).collect();
+        assert_eq!(s.waiter_count(),3);
+        assert_eq!(s.strong_count(),4);
+        drop(w);
+        for h in handles
+        {
+            h.join().unwrap();
+        }
+        assert_eq!(s.waiter_count(),0);
+        assert_eq!(s.strong_count(),1);
+        let weak=s.downgrade();
+        assert_eq!(s.weak_count(),1);
+        drop(weak);
+        assert_eq!(s.weak_count(),0);
+
+        let t=s.clone();
+        assert!(s.ptr_eq(&t));
+        assert!(!s.ptr_eq(&Cura::new(4)));
+        assert_eq!(s.as_ptr(),&*s.read() as *const i32);
+        assert_eq!(unsafe{*t.as_ptr()},4);
+    }
+    #[test]
     fn hash()
     {
         use std::collections::HashMap;
@@ -942,6 +5394,54 @@ mod tests {
         // clones share the same data
         let d = a.clone();
         assert!(a == d);
+        // and are equal without locking
+        let w = a.write();
+        assert!(a == d);
+        assert_eq!(a.cmp(&d), std::cmp::Ordering::Equal);
+        drop(w);
+        let nan = Cura::new(f64::NAN);
+        assert!(nan == nan.clone());
+        assert!(nan != Cura::new(f64::NAN));
+        // comparing both ways round with writers about cant deadlock
+        let a = Cura::with_policy(0, Policy::WriterPreferring);
+        let b = Cura::with_policy(0, Policy::WriterPreferring);
+        let handles: Vec<_> = (0..4).map(|i| {
+            let (a, b) = (a.clone(), b.clone());
+            std::thread::spawn(move || {
+                for _ in 0..500 {
+                    match i {
+                        0 => { let _ = a == b; },
+                        1 => { let _ = b.cmp(&a); },
+                        2 => { *a.write() += 1; },
+                        _ => { *b.write() += 1; },
+                    }
+                }
+            })
+        }).collect();
+        for h in handles {
+            h.join().unwrap();
+        }
+        assert!(a == b);
+    }
+    #[test]
+    fn by_address()
+    {
+        use std::collections::{HashMap,BTreeSet};
+        let a = Cura::new(vec![1]);
+        let b = Cura::new(vec![1]);
+        let mut map = HashMap::new();
+        map.insert(ByAddress(a.clone()), "a");
+        map.insert(ByAddress(b.clone()), "b");
+        assert_eq!(map.len(), 2);
+        // mutating keys is fine, and nothing gets locked to look them up
+        a.write().push(2);
+        let w = b.write();
+        assert_eq!(map[&ByAddress(a.clone())], "a");
+        assert_eq!(map[&ByAddress(b.clone())], "b");
+        drop(w);
+        let set: BTreeSet<_> = [ByAddress(a.clone()), ByAddress(b), ByAddress(a)].into_iter().collect();
+        assert_eq!(set.len(), 2);
+        assert!(set.iter().any(|k| k.read().len() == 2));
     }
     #[test]
     fn debug_and_display()
@@ -954,8 +5454,293 @@ mod tests {
             }
         }
         let c = Cura::new(Foo(42));
-        assert_eq!(format!("{:?}", c), "Foo(42)");
+        assert_eq!(format!("{:?}", c), "Cura { data: Foo(42), state: Free, waiters: 0 }");
         assert_eq!(format!("{}", c), "Foo(42)");
+        {
+            let _r=c.read();
+            assert_eq!(format!("{:?}", c), "Cura { data: Foo(42), state: Read(1), waiters: 0 }");
+        }
+        //  no deadlocking on ourselves
+        let w=c.write();
+        let d=c.clone();
+        let h=std::thread::spawn(move||{
+            let _r=d.read();
+        });
+        wait_for_queue(&c,1);
+        assert_eq!(format!("{:?}", c), "Cura { data: <locked>, state: Write, waiters: 1 }");
+        assert_eq!(format!("{}", c), "<locked>");
+        assert_eq!(format!("{:#?}", c), "Cura {
+    data: <locked>,
+    state: Write,
+    waiters: 1,
+    poisoned: false,
+    policy: Barging,
+    strong: 2,
+    weak: 0,
+}");
+        drop(w);
+        h.join().unwrap();
+    }
+    #[test]
+    fn weak_references()
+    {
+        static DROPS: AtomicUsize = AtomicUsize::new(0);
+        struct Node{
+            parent:Option<WeakCura<Node>>,
+            children:Vec<Cura<Node>>,
+        }
+        impl Drop for Node {
+            fn drop(&mut self) {
+                DROPS.fetch_add(1, Relaxed);
+            }
+        }
+        /*  parent owns the children , children point back weakly*/
+        let root=Cura::new(Node{parent:None,children:Vec::new()});
+        for _ in 0..3 {
+            let child=Cura::new(Node{parent:Some(root.downgrade()),children:Vec::new()});
+            root.write().children.push(child);
+        }
+        let weak=root.downgrade();
+        assert_eq!(weak.strong_count(),1);
+        assert_eq!(weak.weak_count(),4);
+        {
+            let first=root.read().children[0].clone();
+            let parent=first.read().parent.as_ref().unwrap().upgrade().unwrap();
+            assert_eq!(parent.read().children.len(),3);
+            assert_eq!(weak.strong_count(),2);
+        }
+        /*  dropping the root drops the whole tree*/
+        drop(root);
+        assert_eq!(DROPS.load(Relaxed),4);
+        assert!(weak.upgrade().is_none());
+        assert_eq!(weak.strong_count(),0);
+        assert_eq!(weak.weak_count(),1);
+        let weak2=weak.clone();
+        drop(weak);
+        assert!(weak2.upgrade().is_none());
+    }
+    #[test]
+    fn try_locks()
+    {
+        let s=Cura::new(1);
+        {
+            let r=s.read();
+            assert!(s.try_read().is_some());
+            assert!(s.try_write().is_none());
+            drop(r);
+        }
+        {
+            let _w=s.write();
+            assert!(s.try_read().is_none());
+            assert!(s.try_write().is_none());
+            /*  other threads back off too instead of queueing*/
+            let c=s.clone();
+            std::thread::spawn(move||{
+                assert!(c.try_read().is_none());
+                assert!(c.try_write().is_none());
+            }).join().unwrap();
+        }
+        *s.try_write().unwrap()+=1;
+        assert_eq!(*s.try_read().unwrap(),2);
+    }
+    #[test]
+    fn timeouts()
+    {
+        use std::time::Duration;
+        let s=Cura::new(0);
+        let w=s.write();
+        /*  a bunch of waiters that give up while queued*/
+        let mut threads=Vec::new();
+        for i in 0..10 {
+            let c=s.clone();
+            threads.push(std::thread::spawn(move||{
+                if i%2==0 {
+                    assert!(c.read_timeout(Duration::from_millis(50)).is_none());
+                }else{
+                    assert!(c.write_timeout(Duration::from_millis(50)).is_none());
+                }
+            }));
+        }
+        /*  and some that are patient enough*/
+        let mut patient=Vec::new();
+        for i in 0..4 {
+            let c=s.clone();
+            patient.push(std::thread::spawn(move||{
+                if i%2==0 {
+                    assert!(c.read_timeout(Duration::from_secs(60)).is_some());
+                }else{
+                    *c.write_timeout(Duration::from_secs(60)).unwrap()+=1;
+                }
+            }));
+        }
+        while let Some(t)=threads.pop()
+        {
+            t.join().unwrap();
+        }
+        /*  queue should only have the patient ones left*/
+        assert!(s.waiter_count()<=4);
+        drop(w);
+        while let Some(t)=patient.pop()
+        {
+            t.join().unwrap();
+        }
+        assert_eq!(s.waiter_count(),0);
+        assert_eq!(*s.read(),2);
+        assert!(s.write_until(Instant::now()).is_some());
+    }
+    #[test]
+    fn upgradable_reads()
+    {
+        let s=Cura::new(0);
+        /*  only one upgradable at a time, but readers are fine*/
+        let u=s.upgradable_read();
+        assert!(s.try_upgradable_read().is_none());
+        assert!(s.try_write().is_none());
+        let r=s.read();
+        /*  writers and upgraders queue up behind us*/
+        let mut threads=Vec::new();
+        for i in 0..6 {
+            let c=s.clone();
+            threads.push(std::thread::spawn(move||{
+                if i%2==0 {
+                    *c.write()+=1;
+                }else{
+                    let u=c.upgradable_read();
+                    let v=*u;
+                    let mut w=u.upgrade();
+                    /*  nobody got in between*/
+                    assert_eq!(*w,v);
+                    *w+=1;
+                }
+            }));
+        }
+        sleep(100);
+        let u=u.try_upgrade().unwrap_err();
+        drop(r);
+        /*  upgrade while the writers are waiting, they cant sneak in*/
+        let mut w=u.upgrade();
+        assert_eq!(*w,0);
+        *w+=100;
+        drop(w);
+        while let Some(t)=threads.pop()
+        {
+            t.join().unwrap();
+        }
+        assert_eq!(*s.read(),106);
+        assert_eq!(s.state().load(SeqCst)&(WRITER|READERS|UPGRADABLE),0);
+    }
+    #[test]
+    fn downgrade()
+    {
+        let s=Cura::new(0);
+        let mut w=s.write();
+        /*  queue up a writer and some readers behind us*/
+        let mut threads=Vec::new();
+        for i in 0..5 {
+            let c=s.clone();
+            threads.push(std::thread::spawn(move||{
+                if i==0 {
+                    *c.write()+=1;
+                }else{
+                    assert!(*c.read()>=10);
+                }
+            }));
+            sleep(20);
+        }
+        *w=10;
+        let r=w.downgrade();
+        /*  the writer cant sneak in while we read*/
+        assert_eq!(*r,10);
+        assert!(s.try_write().is_none());
+        assert!(s.try_read().is_some());
+        drop(r);
+        while let Some(t)=threads.pop()
+        {
+            t.join().unwrap();
+        }
+        assert_eq!(*s.read(),11);
+    }
+    #[test]
+    fn mapped_guards()
+    {
+        #[derive(Debug)]
+        struct Foo{
+            a:i32,
+            b:Vec<i32>,
+            c:Option<String>,
+        }
+        let s=Cura::new(Foo{a:1,b:vec![1,2,3],c:None});
+        {
+            let mut a=s.write().map(|f|&mut f.a);
+            *a=2;
+            assert!(s.try_read().is_none());
+        }
+        {
+            let b=s.read().map(|f|&f.b[..]).map(|b|&b[1..]);
+            assert_eq!(&*b,&[2,3]);
+            assert!(s.try_read().is_some());
+            assert!(s.try_write().is_none());
+        }
+        /*  failing projections give the guard back*/
+        let g=s.read().filter_map(|f|f.c.as_deref()).unwrap_err();
+        assert_eq!(g.a,2);
+        drop(g);
+        let (g,e)=s.write().try_map(|f|f.b.get_mut(10).ok_or("nope")).unwrap_err();
+        assert_eq!(e,"nope");
+        drop(g);
+        {
+            let mut c=s.write().filter_map(|f|f.b.last_mut()).unwrap();
+            *c=30;
+        }
+        let g=s.read();
+        assert_eq!(g.a,2);
+        assert_eq!(g.b,vec![1,2,30]);
+        drop(g);
+        assert_eq!(s.state().load(SeqCst)&(WRITER|READERS|UPGRADABLE),0);
+    }
+    #[test]
+    fn lock_several()
+    {
+        let a=Cura::new(0);
+        let b=Cura::new(0);
+        let c=Cura::new(0);
+        /*  lock in opposite orders from two threads,classic AB/BA*/
+        let mut threads=Vec::new();
+        for i in 0..4 {
+            let (a,b,c)=(a.clone(),b.clone(),c.clone());
+            threads.push(std::thread::spawn(move||{
+                for _ in 0..500 {
+                    if i%2==0 {
+                        lock_all((WriteLock(&a),WriteLock(&b),ReadLock(&c)),|(mut a,mut b,_c)|{
+                            *a+=1;
+                            *b+=1;
+                        });
+                    }else{
+                        lock_all((ReadLock(&c),WriteLock(&b),WriteLock(&a)),|(_c,mut b,mut a)|{
+                            *a+=1;
+                            *b+=1;
+                        });
+                    }
+                }
+            }));
+        }
+        while let Some(t)=threads.pop()
+        {
+            t.join().unwrap();
+        }
+        assert_eq!(*a.read(),2000);
+        assert_eq!(*b.read(),2000);
+        /*  guards come back in the order they were asked for*/
+        let all=[WriteLock(&c),WriteLock(&b),WriteLock(&a)];
+        lock_all(&all[..],|mut guards|{
+            *guards[0]=1;
+            *guards[1]=2;
+            *guards[2]=3;
+        });
+        assert_eq!((*a.read(),*b.read(),*c.read()),(3,2,1));
+        assert!(std::panic::catch_unwind(std::panic::AssertUnwindSafe(||{
+            lock_all((ReadLock(&a),WriteLock(&a)),|_|{});
+        })).is_err());
     }
     #[test]
     fn it_works() {